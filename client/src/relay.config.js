@@ -1,5 +1,8 @@
+// Compile against the schema exported from the server. To update it, run
+// `cargo run -- export-schema --sdl schema.graphql --json schema.json` in
+// ../server
 module.exports = {
   src: "./src",
-  schema: "./data/schema.graphql",
+  schema: "../server/schema.graphql",
   exclude: ["**/node_modules/**", "**/__mocks__/**", "**/__generated__/**"],
 }
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE squad ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
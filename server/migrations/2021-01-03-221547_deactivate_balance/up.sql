@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE balance ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE invite (
+    id SERIAL PRIMARY KEY,
+    squad_id INTEGER NOT NULL REFERENCES squad(id) ON DELETE CASCADE,
+    created_by INTEGER NOT NULL REFERENCES person(id) ON DELETE CASCADE,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    email VARCHAR(320),
+    expires_at TIMESTAMPTZ NOT NULL,
+    max_uses INTEGER CHECK (max_uses > 0),
+    use_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX ON invite ( squad_id, id );
+CREATE INDEX ON invite ( email, expires_at );
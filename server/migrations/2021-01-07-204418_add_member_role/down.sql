@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE txn DROP COLUMN voided;
+ALTER TABLE txn DROP COLUMN created_by;
+ALTER TABLE balance DROP COLUMN role;
+DROP TYPE member_role;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TYPE member_role AS ENUM ('owner', 'admin', 'member', 'viewer');
+ALTER TABLE balance ADD COLUMN role member_role NOT NULL DEFAULT 'member';
+-- Existing squads are owned by whoever joined them first
+UPDATE balance SET role = 'owner'
+    WHERE id IN (SELECT MIN(id) FROM balance GROUP BY squad_id);
+CREATE INDEX ON balance ( squad_id, role ) WHERE active;
+
+ALTER TABLE txn ADD COLUMN created_by INTEGER REFERENCES person(id) ON DELETE SET NULL;
+ALTER TABLE txn ADD COLUMN voided BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    squad_id INTEGER REFERENCES squad(id),
+    actor_id INTEGER REFERENCES person(id),
+    operation VARCHAR NOT NULL,
+    input JSONB NOT NULL,
+    node_uids UUID[] NOT NULL DEFAULT '{}',
+    request_id VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX ON audit_log ( squad_id, id );
+
+-- The audit log is append-only: entries may never be changed or removed
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE PROCEDURE audit_log_append_only();
+
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE PROCEDURE audit_log_append_only();
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Queries registered by clients using automatic persisted queries, keyed by
+-- the SHA-256 hash of their text, so that they survive restarts and are shared
+-- between servers
+CREATE TABLE persisted_query (
+    sha256_hash VARCHAR PRIMARY KEY,
+    query TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- Keys chosen by clients to identify mutations, so that a retried mutation
+-- returns its original result instead of being applied twice
+CREATE TABLE idempotency_key (
+    person_id INTEGER NOT NULL REFERENCES person(id),
+    key VARCHAR NOT NULL,
+    operation VARCHAR NOT NULL,
+    -- Set in the same transaction as the key is claimed, once the mutation has
+    -- been applied
+    node_id INTEGER REFERENCES node(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (person_id, key)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE txn DROP COLUMN version;
+ALTER TABLE squad DROP COLUMN version;
+ALTER TABLE person DROP COLUMN version;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Incremented whenever a row is changed, so that clients can tell when their
+-- copy is out of date, and changes based on a stale copy can be refused
+ALTER TABLE person ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE squad ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE txn ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
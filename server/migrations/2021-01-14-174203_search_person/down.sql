@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX person_lower_email_prefix;
+DROP INDEX person_full_name_trgm;
+DROP INDEX person_display_name_trgm;
+ALTER TABLE person DROP COLUMN discoverable;
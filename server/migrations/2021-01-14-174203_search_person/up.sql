@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- People who have not chosen to be discoverable can only be found by people
+-- who share a squad with them
+ALTER TABLE person ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Searching people matches substrings of their names, using trigram indexes,
+-- and prefixes of their email addresses
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX person_display_name_trgm ON person USING GIN (display_name gin_trgm_ops);
+CREATE INDEX person_full_name_trgm ON person USING GIN ((first_name || ' ' || last_name) gin_trgm_ops);
+CREATE INDEX person_lower_email_prefix ON person (lower(email) text_pattern_ops);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE person DROP COLUMN name_visibility;
+ALTER TABLE person DROP COLUMN email_visibility;
+DROP TYPE visibility;
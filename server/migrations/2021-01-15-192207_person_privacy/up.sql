@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Who may see a person's email address and their first and last names. Their
+-- display name is always visible, so that they can be found and recognized.
+CREATE TYPE visibility AS ENUM ('private', 'squad_mates', 'public');
+ALTER TABLE person ADD COLUMN email_visibility visibility NOT NULL DEFAULT 'squad_mates';
+ALTER TABLE person ADD COLUMN name_visibility visibility NOT NULL DEFAULT 'squad_mates';
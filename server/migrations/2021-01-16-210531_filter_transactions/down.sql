@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX txn_description_trgm;
+ALTER TABLE txn DROP COLUMN created_at;
+ALTER TABLE txn DROP COLUMN occurred_at;
+ALTER TABLE txn DROP COLUMN amount_cents;
+ALTER TABLE txn DROP COLUMN description;
+ALTER TABLE txn DROP COLUMN kind;
+DROP TYPE transaction_kind;
@@ -0,0 +1,46 @@
+-- Your SQL goes here
+CREATE TYPE transaction_kind AS ENUM ('expense', 'payment', 'adjustment');
+ALTER TABLE txn ADD COLUMN kind transaction_kind NOT NULL DEFAULT 'expense';
+ALTER TABLE txn ADD COLUMN description VARCHAR NOT NULL DEFAULT '';
+-- The total of the transaction's positive balance changes, stored so that
+-- transactions can be filtered and sorted by it
+ALTER TABLE txn ADD COLUMN amount_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE txn ADD COLUMN occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE txn ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+UPDATE txn SET amount_cents = parts.amount_cents
+    FROM (
+        SELECT txn_id, SUM(balance_change_cents) AS amount_cents
+        FROM txn_part
+        WHERE balance_change_cents > 0
+        GROUP BY txn_id
+    ) AS parts
+    WHERE parts.txn_id = txn.id;
+ALTER TABLE txn ALTER COLUMN amount_cents DROP DEFAULT;
+
+-- Transactions recorded before the audit log was introduced can't be dated,
+-- so are given the time of its first entry. They sort before every later
+-- transaction, and among themselves by id, i.e. in the order they were recorded.
+UPDATE txn SET occurred_at = earliest.created_at, created_at = earliest.created_at
+    FROM (SELECT COALESCE(MIN(created_at), NOW()) AS created_at FROM audit_log) AS earliest;
+
+-- Transactions recorded since the audit log was introduced are dated by their
+-- entries in it. Those recorded when somebody left or was removed from a squad
+-- moved their balance to the squad's other members.
+UPDATE txn SET
+        occurred_at = audit_log.created_at,
+        created_at = audit_log.created_at,
+        kind = CASE audit_log.operation
+            WHEN 'removePersonFromSquad' THEN 'adjustment'::transaction_kind
+            WHEN 'leaveSquad' THEN 'adjustment'::transaction_kind
+            ELSE 'expense'::transaction_kind
+        END
+    FROM node, audit_log
+    WHERE node.id = txn.node_id
+        AND node.uid = ANY(audit_log.node_uids)
+        AND audit_log.operation IN ('newTransaction', 'removePersonFromSquad', 'leaveSquad');
+
+CREATE INDEX ON txn ( squad_id, occurred_at, id );
+CREATE INDEX ON txn ( squad_id, created_at, id );
+CREATE INDEX ON txn ( squad_id, amount_cents, id );
+CREATE INDEX txn_description_trgm ON txn USING GIN (description gin_trgm_ops);
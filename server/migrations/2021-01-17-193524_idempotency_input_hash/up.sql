@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- A hash of the input of the mutation which claimed each key, so that reusing
+-- a key for a different mutation can be detected. Null for keys claimed before
+-- it was recorded.
+ALTER TABLE idempotency_key ADD COLUMN input_hash VARCHAR;
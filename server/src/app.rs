@@ -1,11 +1,45 @@
-use crate::graphql::Schema;
+use crate::db::Pool;
+use crate::graphql::{Schema, Viewer};
+use crate::request_id::RequestId;
+use actix_identity::Identity;
 use actix_web::{web, HttpResponse};
+use async_graphql::extensions::TracingConfig;
 use async_graphql::http::{graphiql_source, playground_source, GraphQLPlaygroundConfig};
 use async_graphql_actix_web::{Request, Response};
 
-/// Handler to execute a GraphQL request (either a query or a mutation)
-pub async fn graphql(schema: web::Data<Schema>, req: Request) -> Response {
-    schema.execute(req.into_inner()).await.into()
+/// Handler to execute a GraphQL request (either a query or a mutation) on
+/// behalf of the signed-in viewer, if any
+pub async fn graphql(
+    schema: web::Data<Schema>,
+    pool: web::Data<Pool>,
+    id: Identity,
+    request_id: Option<web::ReqData<RequestId>>,
+    req: Request,
+) -> Response {
+    let viewer = Viewer::from_identity(pool.get_ref(), id.identity()).await;
+    let mut req = req
+        .into_inner()
+        .data(viewer)
+        .data(TracingConfig::default().parent_span(tracing::Span::current()));
+
+    let request_id = request_id.map(|request_id| request_id.into_inner());
+    if let Some(request_id) = &request_id {
+        req = req.data(request_id.clone());
+    }
+
+    let mut response = schema.execute(req).await;
+
+    // Let clients report errors in a way that can be found in the logs
+    if let Some(request_id) = request_id {
+        for error in &mut response.errors {
+            error
+                .extensions
+                .get_or_insert_with(Default::default)
+                .set("requestId", request_id.0.clone());
+        }
+    }
+
+    response.into()
 }
 
 /// Handler to provide graphiql for debuggability. Only exposed when compiled
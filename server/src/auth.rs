@@ -1,20 +1,53 @@
 use crate::googlesignin::{googlesigninerror::GoogleSignInError, GoogleSignInClient, IdInfo};
-use crate::settings::Settings;
-use actix_web::http::{Cookie, StatusCode};
+use crate::settings::ServerSettings;
+use actix_identity::Identity;
+use actix_web::http::{header, Cookie, StatusCode};
 use actix_web::web;
 use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use anyhow::{bail, Result};
+use rand::Rng;
 use serde::Deserialize;
 
+/// Minimum length of the secret used to sign session cookies
+const SESSION_KEY_MIN_LEN: usize = 32;
+
 #[derive(Deserialize)]
 pub struct FormData {
     credential: String,
     g_csrf_token: String,
 }
 
+/// Obtain the secret used to sign session cookies. If none is configured, a
+/// random one is generated, so sessions will not survive a restart.
+pub fn session_key(settings: &ServerSettings) -> Result<Vec<u8>> {
+    if settings.session_key.is_empty() {
+        log::warn!("No session_key configured; sessions will be lost on restart");
+        return Ok(rand::thread_rng().gen::<[u8; 32]>().to_vec());
+    }
+
+    if settings.session_key.len() < SESSION_KEY_MIN_LEN {
+        bail!(
+            "session_key must be at least {} bytes long",
+            SESSION_KEY_MIN_LEN
+        );
+    }
+
+    Ok(settings.session_key.as_bytes().to_vec())
+}
+
+/// Create the client used to verify Google sign-in tokens. It caches Google's
+/// certificates, so a single client should be shared by every worker.
+pub fn google_sign_in_client(settings: &ServerSettings) -> GoogleSignInClient {
+    let mut gsi_client = GoogleSignInClient::new();
+    gsi_client.audiences.push(settings.google_client_id.clone());
+    gsi_client
+}
+
 pub async fn oauth_handler(
     req: HttpRequest,
+    id: Identity,
     form: web::Form<FormData>,
-    settings: web::Data<Settings>,
+    gsi_client: web::Data<GoogleSignInClient>,
 ) -> HttpResponse {
     // Verify double submit token to prevent CSRF.HttpMessage
     let cookie_o: Option<Cookie> = req.cookie("g_csrf_token");
@@ -28,13 +61,18 @@ pub async fn oauth_handler(
     }
 
     // Verify and exchange ID Token for IdInfo.
-    let mut gsi_client = GoogleSignInClient::new();
-    gsi_client
-        .audiences
-        .push(settings.get_ref().server.google_client_id.clone());
     let id_info: Result<IdInfo, GoogleSignInError> = gsi_client.verify(&form.credential).await;
     match id_info {
-        Ok(s) => HttpResponse::Ok().body(format! {"IdInfo is {:?}", s}),
+        Ok(IdInfo {
+            email: Some(email),
+            email_verified: Some(true),
+            ..
+        }) => {
+            // The session identifies the viewer by their verified email
+            id.remember(email);
+            HttpResponse::Found().header(header::LOCATION, "/").finish()
+        }
+        Ok(_) => HttpResponse::build(StatusCode::FORBIDDEN).body("Token has no verified email"),
         Err(_) => HttpResponse::build(StatusCode::FAILED_DEPENDENCY).body("Token failed to verify"),
     }
 }
@@ -0,0 +1,339 @@
+//! Administrative subcommands, which let operators inspect and fix data
+//! without going through GraphQL. Changes are made with the same functions as
+//! the corresponding mutations, and are recorded in the audit log under
+//! operation names prefixed with `cli:`.
+
+use crate::db::{
+    self, models,
+    schema::{balance, node, person, squad, txn, txn_part},
+    traced::*,
+    Pool,
+};
+use crate::graphql::{self, mutations::*, nodes, GlobalId, IdParser};
+use crate::settings::Settings;
+use anyhow::{anyhow, Result};
+use async_graphql::{FieldError, InputType, ID};
+use diesel::{pg::PgConnection, prelude::*};
+use std::{collections::BTreeMap, convert::TryFrom, fs, io, path::PathBuf};
+use structopt::StructOpt;
+use uuid::Uuid;
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Manage the database schema using the migrations built into this binary
+    Migrate(MigrateCommand),
+    /// Create a new person
+    CreatePerson {
+        #[structopt(long)]
+        email: String,
+        #[structopt(long)]
+        display_name: String,
+        #[structopt(long)]
+        first_name: String,
+        #[structopt(long)]
+        last_name: String,
+    },
+    /// Create a new squad, owned by the person with the given email
+    CreateSquad {
+        #[structopt(long)]
+        owner_email: String,
+        #[structopt(long)]
+        display_name: String,
+    },
+    /// Add the person with the given email to a squad
+    AddMember {
+        /// ID of the squad
+        #[structopt(long)]
+        squad_id: String,
+        #[structopt(long)]
+        email: String,
+        /// One of owner, admin, member or viewer
+        #[structopt(long, default_value = "member", parse(try_from_str = parse_role))]
+        role: models::MemberRole,
+    },
+    /// List every balance in a squad, including inactive ones
+    ListBalances {
+        /// ID of the squad
+        #[structopt(long)]
+        squad_id: String,
+    },
+    /// Write the GraphQL schema as SDL and as introspection JSON, for use by the
+    /// client. Prints the SDL if no files are given
+    ExportSchema {
+        /// File to write the SDL to, e.g. schema.graphql
+        #[structopt(long, parse(from_os_str))]
+        sdl: Option<PathBuf>,
+        /// File to write the introspection JSON to, e.g. schema.json
+        #[structopt(long, parse(from_os_str))]
+        json: Option<PathBuf>,
+    },
+    /// Check that every transaction nets to zero, which guarantees that every
+    /// squad's balances add up, and that every transaction's amount is the
+    /// total of its positive balance changes
+    #[structopt(alias = "recompute-balances")]
+    CheckBalances {
+        /// Correct the amounts of transactions whose amount is wrong. Those
+        /// which don't net to zero can't be fixed automatically
+        #[structopt(long)]
+        repair: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum MigrateCommand {
+    /// Run all pending migrations
+    Up,
+    /// Revert the most recently run migration
+    Down,
+    /// List all migrations, and whether they have been run
+    Status,
+    /// Revert and re-run the most recently run migration
+    Redo,
+}
+
+fn parse_role(s: &str) -> Result<models::MemberRole> {
+    match s {
+        "owner" => Ok(models::MemberRole::Owner),
+        "admin" => Ok(models::MemberRole::Admin),
+        "member" => Ok(models::MemberRole::Member),
+        "viewer" => Ok(models::MemberRole::Viewer),
+        _ => Err(anyhow!("Unknown role '{}'", s)),
+    }
+}
+
+/// Describe a change made from the command line for the audit log
+fn audit(operation: &str, input: serde_json::Value) -> Audit {
+    Audit::new(&format!("cli:{}", operation), None, input, None)
+}
+
+fn snapshot<T: InputType>(input: &T) -> serde_json::Value {
+    input
+        .to_value()
+        .into_json()
+        .unwrap_or(serde_json::Value::Null)
+}
+
+/// Execute an administrative subcommand against the database at the given url
+pub async fn run(command: Command, settings: &Settings, database_url: &str) -> Result<()> {
+    let pool = || db::make_pool(&settings.db, database_url);
+    let ids = IdParser::new(&settings.graphql);
+
+    match command {
+        Command::Migrate(command) => migrate(database_url, command),
+        Command::CreatePerson {
+            email,
+            display_name,
+            first_name,
+            last_name,
+        } => {
+            let input = NewPersonInput {
+                email,
+                display_name,
+                first_name,
+                last_name,
+            };
+            let audit = audit("createPerson", snapshot(&input));
+            let payload = new_person(&pool()?, input, audit).await?;
+
+            println!(
+                "Created person {}",
+                GlobalId::from(&payload.person.model.node)
+            );
+            Ok(())
+        }
+        Command::CreateSquad {
+            owner_email,
+            display_name,
+        } => {
+            let pool = pool()?;
+            let owner = nodes::Person::by_email(&pool, owner_email).await?;
+            let input = NewSquadInput { display_name };
+            let audit = audit("createSquad", snapshot(&input));
+            let payload = new_squad(&pool, owner.model.detail.id, input, audit).await?;
+
+            println!(
+                "Created squad {}",
+                GlobalId::from(&payload.squad.model.node)
+            );
+            Ok(())
+        }
+        Command::AddMember {
+            squad_id,
+            email,
+            role,
+        } => {
+            let pool = pool()?;
+            let person = nodes::Person::by_email(&pool, email).await?;
+            let input = AddPersonToSquadInput {
+                person_id: ID::from(GlobalId::from(&person.model.node)),
+                squad_id: ID::from(squad_id),
+            };
+            let mut input_snapshot = snapshot(&input);
+            input_snapshot["role"] = snapshot(&role);
+            let audit = audit("addMember", input_snapshot);
+            let input = ParsedAddPersonToSquadInput::parse(input, &ids)
+                .map_err(|e: FieldError| anyhow!(e.message))?;
+            let payload = add_member(&pool, input, role, audit).await?;
+
+            println!(
+                "Added to squad with balance {}",
+                GlobalId::from(&payload.balance.model.node)
+            );
+            Ok(())
+        }
+        Command::ListBalances { squad_id } => list_balances(&pool()?, &ids, &squad_id).await,
+        Command::ExportSchema { sdl, json } => export_schema(sdl, json).await,
+        Command::CheckBalances { repair } => check_balances(&pool()?, repair).await,
+    }
+}
+
+/// Execute a migrate subcommand against the database at the given url
+fn migrate(database_url: &str, command: MigrateCommand) -> Result<()> {
+    let conn = PgConnection::establish(database_url)?;
+    let mut stdout = io::stdout();
+
+    match command {
+        MigrateCommand::Up => db::migrations::run_pending(&conn, &mut stdout),
+        MigrateCommand::Down => db::migrations::revert_latest(&conn, &mut stdout).map(|_| ()),
+        MigrateCommand::Status => db::migrations::print_status(&conn, &mut stdout),
+        MigrateCommand::Redo => db::migrations::redo_latest(&conn, &mut stdout),
+    }
+}
+
+async fn export_schema(sdl: Option<PathBuf>, json: Option<PathBuf>) -> Result<()> {
+    if sdl.is_none() && json.is_none() {
+        print!("{}", graphql::export_sdl());
+    }
+
+    if let Some(path) = sdl {
+        fs::write(path, graphql::export_sdl())?;
+    }
+
+    if let Some(path) = json {
+        let introspection = graphql::export_introspection().await?;
+        fs::write(path, serde_json::to_string_pretty(&introspection)? + "\n")?;
+    }
+
+    Ok(())
+}
+
+async fn list_balances(pool: &Pool, ids: &IdParser, squad_id: &str) -> Result<()> {
+    let squad_uid = ids
+        .parse(squad_id, models::NodeType::Squad)
+        .map_err(|e: FieldError| anyhow!(e.message))?;
+    let squad = node::table
+        .inner_join(squad::table)
+        .filter(node::uid.eq(squad_uid))
+        .get_result_async::<models::Squad>(pool)
+        .await?;
+
+    let balances = node::table
+        .inner_join(balance::table.inner_join(person::table))
+        .filter(balance::squad_id.eq(squad.detail.id))
+        .order(balance::id)
+        .select((
+            node::uid,
+            balance::id,
+            balance::role,
+            balance::active,
+            person::email,
+        ))
+        .get_results_async::<(Uuid, i32, models::MemberRole, bool, String)>(pool)
+        .await?;
+
+    println!("{}", squad.detail.display_name);
+    for (uid, id, role, active, email) in balances {
+        let total = nodes::Balance::total_cents_by_id(pool, id).await?;
+        println!(
+            "{}  {:<32}  {:<8}  {:<8}  {:>10}",
+            GlobalId::new(models::NodeType::Balance, uid),
+            email,
+            format!("{:?}", role),
+            if active { "active" } else { "inactive" },
+            total
+        );
+    }
+
+    Ok(())
+}
+
+async fn check_balances(pool: &Pool, repair: bool) -> Result<()> {
+    let txns = txn::table
+        .inner_join(node::table)
+        .select((
+            node::uid,
+            txn::id,
+            txn::squad_id,
+            txn::voided,
+            txn::amount_cents,
+        ))
+        .get_results_async::<(Uuid, i32, i32, bool, i32)>(pool)
+        .await?;
+    let parts = txn_part::table
+        .inner_join(txn::table.inner_join(node::table))
+        .select((node::uid, txn_part::balance_change_cents))
+        .get_results_async::<(Uuid, i32)>(pool)
+        .await?;
+
+    // The net and the total of the positive balance changes of each transaction
+    let mut totals = BTreeMap::<Uuid, (i64, i64)>::new();
+    for (uid, change) in parts {
+        let (net, amount) = totals.entry(uid).or_insert((0, 0));
+        *net += i64::from(change);
+        *amount += i64::from(change.max(0));
+    }
+
+    let mut unbalanced = 0;
+    let mut misstated = Vec::new();
+    for (uid, id, squad_id, voided, amount_cents) in &txns {
+        let (net, amount) = totals.get(uid).copied().unwrap_or((0, 0));
+        let global_id = GlobalId::new(models::NodeType::Txn, *uid);
+        if !voided && net != 0 {
+            println!("Transaction {} is off by {} cents", global_id, net);
+            unbalanced += 1;
+        }
+        if amount != i64::from(*amount_cents) {
+            println!(
+                "Transaction {} has amount {} cents, but its changes total {} cents",
+                global_id, amount_cents, amount
+            );
+            misstated.push((*uid, *id, *squad_id, i32::try_from(amount)?));
+        }
+    }
+
+    println!(
+        "Checked {} transactions, of which {} do not net to zero and {} have the wrong amount",
+        txns.len(),
+        unbalanced,
+        misstated.len()
+    );
+
+    if repair && !misstated.is_empty() {
+        let repaired = misstated.len();
+        pool.transaction(move |conn| {
+            for (uid, id, squad_id, amount_cents) in misstated {
+                diesel::update(txn::table.find(id))
+                    .set((
+                        txn::amount_cents.eq(amount_cents),
+                        txn::version.eq(txn::version + 1),
+                    ))
+                    .execute(conn)?;
+                let input = serde_json::json!({ "amountCents": amount_cents });
+                audit("checkBalances", input).record(conn, Some(squad_id), vec![uid])?;
+            }
+            Ok(())
+        })
+        .await?;
+        println!("Corrected the amounts of {} transactions", repaired);
+    } else if !misstated.is_empty() {
+        return Err(anyhow!(
+            "Found transactions with the wrong amount; run with --repair to correct them"
+        ));
+    }
+
+    if unbalanced == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("Found unbalanced transactions"))
+    }
+}
@@ -0,0 +1,188 @@
+//! Database migrations, embedded in the server binary so that the schema can
+//! be managed without installing diesel_cli. Progress is tracked in the same
+//! `__diesel_schema_migrations` table as diesel_cli uses, so the two may be
+//! used interchangeably.
+
+use anyhow::{anyhow, Result};
+use diesel::{
+    connection::SimpleConnection,
+    dsl::sql,
+    pg::PgConnection,
+    prelude::*,
+    sql_types::{Bool, Text},
+};
+use diesel_migrations::{
+    run_migrations, setup_database, Migration, MigrationConnection, RunMigrationsError,
+};
+use std::io::Write;
+
+/// A migration whose SQL is compiled into the binary
+pub struct EmbeddedMigration {
+    pub name: &'static str,
+    version: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl Migration for EmbeddedMigration {
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn run(&self, conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        Ok(conn.batch_execute(self.up)?)
+    }
+
+    fn revert(&self, conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        Ok(conn.batch_execute(self.down)?)
+    }
+}
+
+macro_rules! embed {
+    ($version:literal, $name:literal) => {
+        EmbeddedMigration {
+            name: $name,
+            version: $version,
+            up: include_str!(concat!("../../migrations/", $name, "/up.sql")),
+            down: include_str!(concat!("../../migrations/", $name, "/down.sql")),
+        }
+    };
+}
+
+/// Every migration in the `migrations/` directory, oldest first. New
+/// migrations must be added here in order to be shipped with the binary.
+pub static MIGRATIONS: &[EmbeddedMigration] = &[
+    embed!("00000000000000", "00000000000000_diesel_initial_setup"),
+    embed!("20200627231312", "2020-06-27-231312_create_user"),
+    embed!("20200801181305", "2020-08-01-181305_create_squad"),
+    embed!("20201226215032", "2020-12-26-215032_create_balance"),
+    embed!("20201227024015", "2020-12-27-024015_create_transaction"),
+    embed!("20210102193012", "2021-01-02-193012_archive_squad"),
+    embed!("20210103221547", "2021-01-03-221547_deactivate_balance"),
+    embed!("20210105180233", "2021-01-05-180233_create_invite"),
+    embed!("20210107204418", "2021-01-07-204418_add_member_role"),
+    embed!("20210109171540", "2021-01-09-171540_create_audit_log"),
+    embed!("20210111190204", "2021-01-11-190204_create_persisted_query"),
+    embed!("20210112183517", "2021-01-12-183517_create_idempotency_key"),
+    embed!("20210113201146", "2021-01-13-201146_add_version"),
+    embed!("20210114174203", "2021-01-14-174203_search_person"),
+    embed!("20210115192207", "2021-01-15-192207_person_privacy"),
+    embed!("20210116210531", "2021-01-16-210531_filter_transactions"),
+    embed!("20210117193524", "2021-01-17-193524_idempotency_input_hash"),
+];
+
+/// Run every migration which has not yet been run, oldest first
+pub fn run_pending(conn: &PgConnection, output: &mut dyn Write) -> Result<()> {
+    Ok(run_migrations(
+        conn,
+        MIGRATIONS.iter().map(|m| m as &dyn Migration),
+        output,
+    )?)
+}
+
+/// Revert the most recently run migration, returning it
+pub fn revert_latest(
+    conn: &PgConnection,
+    output: &mut dyn Write,
+) -> Result<&'static EmbeddedMigration> {
+    setup_database(conn)?;
+    let version = conn
+        .latest_run_migration_version()?
+        .ok_or_else(|| anyhow!("No migrations have been run"))?;
+    let migration = MIGRATIONS
+        .iter()
+        .find(|m| m.version == version)
+        .ok_or_else(|| anyhow!("Migration {} is not known to this binary", version))?;
+
+    conn.transaction::<_, anyhow::Error, _>(|| {
+        writeln!(output, "Rolling back migration {}", migration.name)?;
+        migration.revert(conn)?;
+        diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = $1")
+            .bind::<Text, _>(migration.version)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    Ok(migration)
+}
+
+/// Revert and re-run the most recently run migration
+pub fn redo_latest(conn: &PgConnection, output: &mut dyn Write) -> Result<()> {
+    revert_latest(conn, output)?;
+    run_pending(conn, output)
+}
+
+/// List every embedded migration, and whether it has been run
+pub fn print_status(conn: &PgConnection, output: &mut dyn Write) -> Result<()> {
+    setup_database(conn)?;
+    let already_run = conn.previously_run_migration_versions()?;
+
+    for migration in MIGRATIONS {
+        let mark = if already_run.iter().any(|v| v == migration.version) {
+            "X"
+        } else {
+            " "
+        };
+        writeln!(output, "[{}] {}", mark, migration.name)?;
+    }
+
+    for version in already_run
+        .iter()
+        .filter(|v| !MIGRATIONS.iter().any(|m| m.version == v.as_str()))
+    {
+        writeln!(output, "[?] {} (not known to this binary)", version)?;
+    }
+
+    Ok(())
+}
+
+/// Whether any embedded migrations have yet to be run. Unlike the other
+/// functions here this only reads from the database, so that it can be called
+/// by a database user who may not create the migrations table; if the table
+/// doesn't exist, every migration is pending.
+pub fn any_pending(conn: &PgConnection) -> Result<bool> {
+    let table_exists = diesel::select(sql::<Bool>(
+        "to_regclass('__diesel_schema_migrations') IS NOT NULL",
+    ))
+    .get_result::<bool>(conn)?;
+    if !table_exists {
+        return Ok(!MIGRATIONS.is_empty());
+    }
+
+    let already_run =
+        sql::<Text>("SELECT version FROM __diesel_schema_migrations").load::<String>(conn)?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .any(|m| !already_run.iter().any(|v| v == m.version)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MIGRATIONS;
+    use diesel_migrations::{migration_paths_in_directory, version_from_path};
+    use std::path::Path;
+
+    #[test]
+    fn test_embedded_migrations_match_directory() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+        let mut on_disk = migration_paths_in_directory(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                (
+                    version_from_path(&entry.path()).unwrap(),
+                    entry.file_name().to_string_lossy().into_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        on_disk.sort();
+
+        let embedded = MIGRATIONS
+            .iter()
+            .map(|m| (m.version.to_string(), m.name.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(on_disk, embedded);
+    }
+}
@@ -1,8 +1,12 @@
-use super::schema::{balance, node, person, squad, txn, txn_part};
+use super::schema::{
+    audit_log, balance, idempotency_key, invite, node, persisted_query, person, squad, txn,
+    txn_part,
+};
+use chrono::{DateTime, Utc};
 use diesel_derive_enum::DbEnum;
 use uuid::Uuid;
 
-#[derive(Debug, DbEnum)]
+#[derive(Debug, DbEnum, Copy, Clone, Eq, Hash, PartialEq)]
 pub enum NodeType {
     Person,
     Squad,
@@ -10,7 +14,51 @@ pub enum NodeType {
     Txn,
 }
 
-#[derive(Queryable, Identifiable)]
+/// A member's role in a squad, which determines what they may do in it
+#[derive(Debug, DbEnum, async_graphql::Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MemberRole {
+    Owner,
+    Admin,
+    Member,
+    Viewer,
+}
+
+impl MemberRole {
+    /// Whether the role may rename or archive the squad, manage its members,
+    /// and void other members' transactions
+    pub fn can_manage(self) -> bool {
+        matches!(self, MemberRole::Owner | MemberRole::Admin)
+    }
+
+    /// Whether the role may record transactions and bring in new members
+    pub fn can_contribute(self) -> bool {
+        !matches!(self, MemberRole::Viewer)
+    }
+}
+
+/// What a transaction records
+#[derive(Debug, DbEnum, async_graphql::Enum, Copy, Clone, Eq, PartialEq)]
+pub enum TransactionKind {
+    /// Somebody paid for something on behalf of others
+    Expense,
+    /// Somebody paid somebody else back
+    Payment,
+    /// Balances were moved, e.g. when somebody left the squad
+    Adjustment,
+}
+
+/// Who may see one of a person's details
+#[derive(Debug, DbEnum, async_graphql::Enum, Copy, Clone, Eq, PartialEq)]
+pub enum Visibility {
+    /// Only the person themselves
+    Private,
+    /// The person and the people who share a squad with them
+    SquadMates,
+    /// Anybody
+    Public,
+}
+
+#[derive(Clone, Queryable, Identifiable)]
 #[table_name = "node"]
 pub struct Node {
     pub id: i32,
@@ -25,7 +73,7 @@ pub struct NewNode {
     pub node_type: NodeType,
 }
 
-#[derive(Queryable, Identifiable)]
+#[derive(Clone, Queryable, Identifiable)]
 #[table_name = "person"]
 pub struct PersonDetail {
     pub id: i32,
@@ -34,23 +82,29 @@ pub struct PersonDetail {
     pub email: String,
     pub first_name: String,
     pub last_name: String,
+    pub version: i32,
+    pub discoverable: bool,
+    pub email_visibility: Visibility,
+    pub name_visibility: Visibility,
 }
 
-#[derive(Queryable)]
+#[derive(Clone, Queryable)]
 pub struct Person {
     pub node: Node,
     pub detail: PersonDetail,
 }
 
-#[derive(Queryable, Identifiable)]
+#[derive(Clone, Queryable, Identifiable)]
 #[table_name = "squad"]
 pub struct SquadDetail {
     pub id: i32,
     pub node_id: i32,
     pub display_name: String,
+    pub archived: bool,
+    pub version: i32,
 }
 
-#[derive(Queryable)]
+#[derive(Clone, Queryable)]
 pub struct Squad {
     pub node: Node,
     pub detail: SquadDetail,
@@ -66,6 +120,18 @@ pub struct NewPerson<'a> {
     pub last_name: &'a str,
 }
 
+#[derive(AsChangeset)]
+#[table_name = "person"]
+pub struct PersonChanges<'a> {
+    pub email: Option<&'a str>,
+    pub display_name: Option<&'a str>,
+    pub first_name: Option<&'a str>,
+    pub last_name: Option<&'a str>,
+    pub discoverable: Option<bool>,
+    pub email_visibility: Option<Visibility>,
+    pub name_visibility: Option<Visibility>,
+}
+
 #[derive(Insertable)]
 #[table_name = "squad"]
 pub struct NewSquad<'a> {
@@ -73,16 +139,18 @@ pub struct NewSquad<'a> {
     pub display_name: &'a str,
 }
 
-#[derive(Queryable, Identifiable)]
+#[derive(Clone, Queryable, Identifiable)]
 #[table_name = "balance"]
 pub struct BalanceDetail {
     pub id: i32,
     pub node_id: i32,
     pub person_id: i32,
     pub squad_id: i32,
+    pub active: bool,
+    pub role: MemberRole,
 }
 
-#[derive(Queryable)]
+#[derive(Clone, Queryable)]
 pub struct Balance {
     pub node: Node,
     pub detail: BalanceDetail,
@@ -94,17 +162,26 @@ pub struct NewBalance {
     pub node_id: i32,
     pub person_id: i32,
     pub squad_id: i32,
+    pub role: MemberRole,
 }
 
-#[derive(Queryable, Identifiable)]
+#[derive(Clone, Queryable, Identifiable)]
 #[table_name = "txn"]
 pub struct TransactionDetail {
     pub id: i32,
     pub node_id: i32,
     pub squad_id: i32,
+    pub created_by: Option<i32>,
+    pub voided: bool,
+    pub version: i32,
+    pub kind: TransactionKind,
+    pub description: String,
+    pub amount_cents: i32,
+    pub occurred_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
 }
 
-#[derive(Queryable)]
+#[derive(Clone, Queryable)]
 pub struct Transaction {
     pub node: Node,
     pub detail: TransactionDetail,
@@ -112,9 +189,14 @@ pub struct Transaction {
 
 #[derive(Insertable)]
 #[table_name = "txn"]
-pub struct NewTransaction {
+pub struct NewTransaction<'a> {
     pub node_id: i32,
     pub squad_id: i32,
+    pub created_by: Option<i32>,
+    pub kind: TransactionKind,
+    pub description: &'a str,
+    pub amount_cents: i32,
+    pub occurred_at: DateTime<Utc>,
 }
 
 #[derive(Queryable, Identifiable)]
@@ -133,3 +215,75 @@ pub struct NewTransactionPart {
     pub balance_id: i32,
     pub balance_change_cents: i32,
 }
+
+#[derive(Queryable, Identifiable)]
+#[table_name = "invite"]
+pub struct Invite {
+    pub id: i32,
+    pub squad_id: i32,
+    pub created_by: i32,
+    pub token: String,
+    pub email: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "invite"]
+pub struct NewInvite<'a> {
+    pub squad_id: i32,
+    pub created_by: i32,
+    pub token: &'a str,
+    pub email: Option<&'a str>,
+    pub expires_at: DateTime<Utc>,
+    pub max_uses: Option<i32>,
+}
+
+impl Invite {
+    /// Whether the invite may still be accepted at the given time
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at > now && self.max_uses.map_or(true, |max| self.use_count < max)
+    }
+}
+
+#[derive(Queryable, Identifiable)]
+#[table_name = "audit_log"]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub squad_id: Option<i32>,
+    pub actor_id: Option<i32>,
+    pub operation: String,
+    pub input: serde_json::Value,
+    pub node_uids: Vec<Uuid>,
+    pub request_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditLogEntry<'a> {
+    pub squad_id: Option<i32>,
+    pub actor_id: Option<i32>,
+    pub operation: &'a str,
+    pub input: &'a serde_json::Value,
+    pub node_uids: Vec<Uuid>,
+    pub request_id: Option<&'a str>,
+}
+
+#[derive(Insertable)]
+#[table_name = "persisted_query"]
+pub struct NewPersistedQuery<'a> {
+    pub sha256_hash: &'a str,
+    pub query: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "idempotency_key"]
+pub struct NewIdempotencyKey<'a> {
+    pub person_id: i32,
+    pub key: &'a str,
+    pub operation: &'a str,
+    pub input_hash: &'a str,
+}
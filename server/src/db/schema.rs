@@ -1,9 +1,49 @@
+table! {
+    audit_log (id) {
+        id -> Int4,
+        squad_id -> Nullable<Int4>,
+        actor_id -> Nullable<Int4>,
+        operation -> Varchar,
+        input -> Jsonb,
+        node_uids -> Array<Uuid>,
+        request_id -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     balance (id) {
         id -> Int4,
         node_id -> Int4,
         person_id -> Int4,
         squad_id -> Int4,
+        active -> Bool,
+        role -> crate::db::models::MemberRoleMapping,
+    }
+}
+
+table! {
+    idempotency_key (person_id, key) {
+        person_id -> Int4,
+        key -> Varchar,
+        operation -> Varchar,
+        node_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        input_hash -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    invite (id) {
+        id -> Int4,
+        squad_id -> Int4,
+        created_by -> Int4,
+        token -> Varchar,
+        email -> Nullable<Varchar>,
+        expires_at -> Timestamptz,
+        max_uses -> Nullable<Int4>,
+        use_count -> Int4,
+        created_at -> Timestamptz,
     }
 }
 
@@ -23,6 +63,18 @@ table! {
         email -> Varchar,
         first_name -> Varchar,
         last_name -> Varchar,
+        version -> Int4,
+        discoverable -> Bool,
+        email_visibility -> crate::db::models::VisibilityMapping,
+        name_visibility -> crate::db::models::VisibilityMapping,
+    }
+}
+
+table! {
+    persisted_query (sha256_hash) {
+        sha256_hash -> Varchar,
+        query -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -31,6 +83,8 @@ table! {
         id -> Int4,
         node_id -> Int4,
         display_name -> Varchar,
+        archived -> Bool,
+        version -> Int4,
     }
 }
 
@@ -39,6 +93,14 @@ table! {
         id -> Int4,
         node_id -> Int4,
         squad_id -> Int4,
+        created_by -> Nullable<Int4>,
+        voided -> Bool,
+        version -> Int4,
+        kind -> crate::db::models::TransactionKindMapping,
+        description -> Varchar,
+        amount_cents -> Int4,
+        occurred_at -> Timestamptz,
+        created_at -> Timestamptz,
     }
 }
 
@@ -51,14 +113,32 @@ table! {
     }
 }
 
+joinable!(audit_log -> person (actor_id));
+joinable!(audit_log -> squad (squad_id));
 joinable!(balance -> node (node_id));
 joinable!(balance -> person (person_id));
 joinable!(balance -> squad (squad_id));
+joinable!(idempotency_key -> node (node_id));
+joinable!(idempotency_key -> person (person_id));
+joinable!(invite -> person (created_by));
+joinable!(invite -> squad (squad_id));
 joinable!(person -> node (node_id));
 joinable!(squad -> node (node_id));
 joinable!(txn -> node (node_id));
+joinable!(txn -> person (created_by));
 joinable!(txn -> squad (squad_id));
 joinable!(txn_part -> balance (balance_id));
 joinable!(txn_part -> txn (txn_id));
 
-allow_tables_to_appear_in_same_query!(balance, node, person, squad, txn, txn_part,);
+allow_tables_to_appear_in_same_query!(
+    audit_log,
+    balance,
+    idempotency_key,
+    invite,
+    node,
+    person,
+    persisted_query,
+    squad,
+    txn,
+    txn_part,
+);
@@ -0,0 +1,253 @@
+//! A connection which runs each statement within a span, so that it appears in
+//! traces, and equivalents of tokio_diesel's extension traits which carry the
+//! caller's span over to the thread the statements run on. Import
+//! `db::traced::*` rather than `tokio_diesel::*`.
+
+pub use tokio_diesel::{AsyncError, AsyncResult, OptionalExtension};
+
+use super::Pool;
+use async_trait::async_trait;
+use diesel::{
+    connection::{AnsiTransactionManager, Connection, SimpleConnection},
+    deserialize::{Queryable, QueryableByName},
+    pg::{Pg, PgConnection, PgQueryBuilder},
+    query_builder::{AsQuery, QueryBuilder, QueryFragment, QueryId},
+    query_dsl::methods::LoadQuery,
+    result::{ConnectionResult, QueryResult},
+    sql_types::HasSqlType,
+    RunQueryDsl,
+};
+use std::ops::Deref;
+use tracing::{field, Instrument, Span};
+
+fn span(name: &'static str) -> Span {
+    tracing::info_span!(
+        "db",
+        otel.name = name,
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.statement = field::Empty,
+    )
+}
+
+/// A span for running the given query, recording its SQL (without any bound
+/// values) only if the span is being recorded
+fn query_span<T: QueryFragment<Pg>>(query: &T) -> Span {
+    let span = span("db.query");
+    if !span.is_disabled() {
+        let mut builder = PgQueryBuilder::default();
+        if query.to_sql(&mut builder).is_ok() {
+            span.record("db.statement", &builder.finish().as_str());
+        }
+    }
+    span
+}
+
+/// Wrap a closure so that it runs within `span`. tokio_diesel runs closures on
+/// a blocking thread, which doesn't otherwise know which span they belong to.
+fn within<C, R>(span: Span, f: impl FnOnce(&C) -> R) -> impl FnOnce(&C) -> R {
+    move |conn| span.in_scope(|| f(conn))
+}
+
+/// The pool's connections, which are otherwise a `PgConnection`. Statements
+/// run directly on the inner connection, e.g. migrations, aren't traced.
+pub struct TracedConnection(PgConnection);
+
+impl Deref for TracedConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.0
+    }
+}
+
+impl SimpleConnection for TracedConnection {
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        self.0.batch_execute(query)
+    }
+}
+
+impl Connection for TracedConnection {
+    type Backend = Pg;
+    type TransactionManager = AnsiTransactionManager;
+
+    fn establish(database_url: &str) -> ConnectionResult<TracedConnection> {
+        PgConnection::establish(database_url).map(TracedConnection)
+    }
+
+    fn execute(&self, query: &str) -> QueryResult<usize> {
+        self.0.execute(query)
+    }
+
+    fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+    where
+        T: AsQuery,
+        T::Query: QueryFragment<Pg> + QueryId,
+        Pg: HasSqlType<T::SqlType>,
+        U: Queryable<T::SqlType, Pg>,
+    {
+        let query = source.as_query();
+        query_span(&query).in_scope(|| self.0.query_by_index(query))
+    }
+
+    fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+    where
+        T: QueryFragment<Pg> + QueryId,
+        U: QueryableByName<Pg>,
+    {
+        query_span(source).in_scope(|| self.0.query_by_name(source))
+    }
+
+    fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        query_span(source).in_scope(|| self.0.execute_returning_count(source))
+    }
+
+    fn transaction_manager(&self) -> &AnsiTransactionManager {
+        self.0.transaction_manager()
+    }
+}
+
+#[async_trait]
+pub trait AsyncConnection {
+    async fn transaction<R, Func>(&self, f: Func) -> AsyncResult<R>
+    where
+        R: 'static + Send,
+        Func: 'static + FnOnce(&TracedConnection) -> QueryResult<R> + Send;
+}
+
+#[async_trait]
+impl AsyncConnection for Pool {
+    async fn transaction<R, Func>(&self, f: Func) -> AsyncResult<R>
+    where
+        R: 'static + Send,
+        Func: 'static + FnOnce(&TracedConnection) -> QueryResult<R> + Send,
+    {
+        let span = span("db.transaction");
+        tokio_diesel::AsyncConnection::transaction(self, within(span.clone(), f))
+            .instrument(span)
+            .await
+    }
+}
+
+#[async_trait]
+pub trait AsyncRunQueryDsl: Sized {
+    async fn get_result_async<U>(self, pool: &Pool) -> AsyncResult<U>
+    where
+        U: 'static + Send,
+        Self: LoadQuery<TracedConnection, U>;
+
+    async fn get_results_async<U>(self, pool: &Pool) -> AsyncResult<Vec<U>>
+    where
+        U: 'static + Send,
+        Self: LoadQuery<TracedConnection, U>;
+}
+
+#[async_trait]
+impl<T> AsyncRunQueryDsl for T
+where
+    T: 'static + Send + RunQueryDsl<TracedConnection>,
+{
+    async fn get_result_async<U>(self, pool: &Pool) -> AsyncResult<U>
+    where
+        U: 'static + Send,
+        Self: LoadQuery<TracedConnection, U>,
+    {
+        let run = within(Span::current(), move |conn| self.get_result(conn));
+        tokio_diesel::AsyncConnection::run(pool, run).await
+    }
+
+    async fn get_results_async<U>(self, pool: &Pool) -> AsyncResult<Vec<U>>
+    where
+        U: 'static + Send,
+        Self: LoadQuery<TracedConnection, U>,
+    {
+        let run = within(Span::current(), move |conn| self.load(conn));
+        tokio_diesel::AsyncConnection::run(pool, run).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::person;
+    use diesel::prelude::*;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// The ID of a span's parent, and the values of its fields
+    type Recorded = (Option<u64>, HashMap<String, String>);
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<HashMap<u64, Recorded>>>);
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+        fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.id().into_u64());
+            let mut fields = HashMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .insert(id.into_u64(), (parent, fields));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            if let Some((_, fields)) = self.0.lock().unwrap().get_mut(&id.into_u64()) {
+                values.record(&mut Fields(fields));
+            }
+        }
+    }
+
+    #[test]
+    fn test_statements_within_transaction() {
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            // What the connection does for each statement run by a closure
+            // passed to Pool::transaction, short of running it
+            let query = person::table.filter(person::id.eq(1)).select(person::email);
+            let run = within(span("db.transaction"), |query: &_| {
+                query_span(query).in_scope(|| ())
+            });
+            run(&query);
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        let name = |fields: &HashMap<String, String>| fields["otel.name"].clone();
+        let (parent, fields) = spans
+            .values()
+            .find(|(_, fields)| name(fields) == "db.query")
+            .expect("A span was recorded for the statement");
+        assert_eq!(name(&spans[&parent.unwrap()].1), "db.transaction");
+        assert_eq!(
+            fields["db.statement"],
+            r#"SELECT "person"."email" FROM "person" WHERE "person"."id" = $1"#
+        );
+    }
+}
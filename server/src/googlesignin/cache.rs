@@ -1,29 +1,63 @@
 use crate::googlesignin::googlesigninerror::GoogleSignInError;
+use crate::telemetry;
 use bytes::buf::ext::BufExt;
 use futures::future::{FutureExt, Shared};
 use hyper::client::{Client as HyperClient, HttpConnector};
+use hyper::{Body, Request};
 use hyper_rustls::HttpsConnector;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::btree_map::Range;
 use std::collections::BTreeMap;
 use std::ops::{
     Bound,
     Bound::{Included, Unbounded},
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Duration, Instant};
+use tracing::{field, Instrument};
 
 pub type HttpClient = HyperClient<HttpsConnector<HttpConnector>>;
 
 #[derive(Clone)]
 pub struct Cache {
     state: Arc<Mutex<RefreshState>>,
+    stats: Arc<Counters>,
+}
+
+/// Running totals of how the cache has been used, for reporting metrics
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    /// Lookups answered from certificates which were already cached
+    pub hits: u64,
+    /// Requests made to Google for certificates
+    pub refreshes: u64,
+    /// Requests made to Google for certificates which failed
+    pub refresh_errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    refreshes: AtomicU64,
+    refresh_errors: AtomicU64,
 }
 
 impl Cache {
     pub fn new() -> Cache {
         Cache {
             state: Arc::new(Mutex::new(RefreshState::Uninitialized)),
+            stats: Arc::new(Counters::default()),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.stats.hits.load(Ordering::Relaxed),
+            refreshes: self.stats.refreshes.load(Ordering::Relaxed),
+            refresh_errors: self.stats.refresh_errors.load(Ordering::Relaxed),
         }
     }
 
@@ -39,22 +73,28 @@ impl Cache {
             match state {
                 RefreshState::Expired(fut) => fut.clone(),
                 RefreshState::Uninitialized => {
-                    let fut = Cache::refresh_with(self.state.clone(), client.clone())
-                        .boxed_local()
-                        .shared();
+                    let fut =
+                        Cache::refresh_with(self.state.clone(), self.stats.clone(), client.clone())
+                            .boxed()
+                            .shared();
                     *state = RefreshState::Expired(fut.clone());
                     fut
                 }
                 RefreshState::Ready(certs) => {
                     if certs.is_expired() {
-                        let fut = Cache::refresh_with(self.state.clone(), client.clone())
-                            .boxed_local()
-                            .shared();
+                        let fut = Cache::refresh_with(
+                            self.state.clone(),
+                            self.stats.clone(),
+                            client.clone(),
+                        )
+                        .boxed()
+                        .shared();
                         *state = RefreshState::Expired(fut.clone());
                         fut
                     } else {
+                        self.stats.hits.fetch_add(1, Ordering::Relaxed);
                         let certs = Arc::clone(certs);
-                        (async move { Ok(certs) }).boxed_local().shared()
+                        (async move { Ok(certs) }).boxed().shared()
                     }
                 }
             }
@@ -63,12 +103,29 @@ impl Cache {
         fut.await
     }
 
+    /// Describe the cached certificates without refreshing them
+    pub fn status(&self) -> CacheStatus {
+        match &*self.state.lock().unwrap() {
+            RefreshState::Uninitialized => CacheStatus::Empty,
+            RefreshState::Expired(_) => CacheStatus::Refreshing,
+            RefreshState::Ready(certs) if certs.is_expired() => CacheStatus::Expired,
+            RefreshState::Ready(_) => CacheStatus::Fresh,
+        }
+    }
+
     async fn refresh_with(
         state: Arc<Mutex<RefreshState>>,
+        stats: Arc<Counters>,
         client: HttpClient,
     ) -> Result<Arc<Certificates>, GoogleSignInError> {
-        let certs = Certificates::get_with_http_client(&client).await?;
-        let certs = Arc::new(certs);
+        stats.refreshes.fetch_add(1, Ordering::Relaxed);
+        let certs = match Certificates::get_with_http_client(&client).await {
+            Ok(certs) => Arc::new(certs),
+            Err(e) => {
+                stats.refresh_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
         let mut state = state.lock().unwrap();
         *state = RefreshState::Ready(Arc::clone(&certs));
         Ok(certs)
@@ -76,9 +133,23 @@ impl Cache {
 }
 
 type Promise = std::pin::Pin<
-    Box<dyn std::future::Future<Output = Result<Arc<Certificates>, GoogleSignInError>>>,
+    Box<dyn std::future::Future<Output = Result<Arc<Certificates>, GoogleSignInError>> + Send>,
 >;
 
+/// The state of the certificate cache, as reported by the readiness check
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    /// No certificates have been fetched yet. They are fetched on first use
+    Empty,
+    /// Certificates are being fetched
+    Refreshing,
+    /// Certificates are cached, and will be used to verify tokens
+    Fresh,
+    /// Certificates are cached but out of date, so will be fetched on next use
+    Expired,
+}
+
 enum RefreshState {
     Ready(Arc<Certificates>),
     Expired(Shared<Promise>),
@@ -122,8 +193,23 @@ impl Certificates {
     async fn get_with_http_client(client: &HttpClient) -> Result<Certificates, GoogleSignInError> {
         const URL: &str = "https://www.googleapis.com/oauth2/v2/certs";
 
-        let url = URL.parse().unwrap();
-        let response = client.get(url).await?;
+        let span = tracing::info_span!(
+            "Google certificates",
+            otel.name = "GET googleapis.com/oauth2/v2/certs",
+            otel.kind = "client",
+            http.method = "GET",
+            http.url = URL,
+            http.status_code = field::Empty,
+        );
+        let response = async {
+            let mut request = Request::get(URL).body(Body::empty()).unwrap();
+            telemetry::inject_context(request.headers_mut());
+            let response = client.request(request).await?;
+            tracing::Span::current().record("http.status_code", &response.status().as_u16());
+            Ok::<_, GoogleSignInError>(response)
+        }
+        .instrument(span)
+        .await?;
         let expiry = response
             .headers()
             .get("Cache-Control")
@@ -1,6 +1,8 @@
 mod cache;
 pub mod googlesigninerror;
 
+pub use crate::googlesignin::cache::{CacheStats, CacheStatus};
+
 use crate::googlesignin::cache::{Cache, Certificates, HttpClient};
 use crate::googlesignin::googlesigninerror::GoogleSignInError;
 use hyper::client::Client as HyperClient;
@@ -29,6 +31,16 @@ impl GoogleSignInClient {
         }
     }
 
+    /// Describes the cached certificates, without fetching them
+    pub fn cache_status(&self) -> CacheStatus {
+        self.cache.status()
+    }
+
+    /// Counts how often the cached certificates have been used and refreshed
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
     /// Verifies that the token is signed by Google's OAuth cerificate,
     /// and check that it has a valid issuer, audience, and hosted domain.
     ///
@@ -0,0 +1,107 @@
+use super::{
+    nodes::{Person, Squad},
+    GlobalId,
+};
+use crate::db::{models, schema::node, traced::*, Pool};
+use async_graphql::{Context, FieldError, FieldResult, OutputJson, ID};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A record of a mutation, kept for accountability. Entries can never be
+/// changed or removed.
+pub struct AuditLogEntry {
+    pub model: models::AuditLogEntry,
+    pub node_ids: Vec<GlobalId>,
+}
+
+impl AuditLogEntry {
+    /// Resolve the types of the nodes referred to by a page of entries with a
+    /// single query. Nodes are never deleted, so every UID must be found.
+    pub async fn load_all(
+        pool: &Pool,
+        models: Vec<models::AuditLogEntry>,
+    ) -> AsyncResult<Vec<AuditLogEntry>> {
+        let uids: Vec<Uuid> = models
+            .iter()
+            .flat_map(|model| model.node_uids.iter().cloned())
+            .collect();
+        let nodes: HashMap<Uuid, GlobalId> = node::table
+            .filter(node::uid.eq_any(uids))
+            .get_results_async::<models::Node>(pool)
+            .await?
+            .iter()
+            .map(|node| (node.uid, GlobalId::from(node)))
+            .collect();
+
+        models
+            .into_iter()
+            .map(|model| {
+                let node_ids = model
+                    .node_uids
+                    .iter()
+                    .map(|uid| {
+                        nodes.get(uid).copied().ok_or_else(|| {
+                            log::error!(
+                                "Audit log entry {} refers to unknown node {}",
+                                model.id,
+                                uid
+                            );
+                            AsyncError::Error(diesel::result::Error::NotFound)
+                        })
+                    })
+                    .collect::<AsyncResult<_>>()?;
+                Ok(AuditLogEntry { model, node_ids })
+            })
+            .collect()
+    }
+}
+
+#[async_graphql::Object]
+impl AuditLogEntry {
+    /// Name of the mutation, e.g. `newTransaction`
+    pub async fn operation(&self) -> &str {
+        &self.model.operation
+    }
+
+    /// The input the mutation was called with, with any secrets redacted
+    pub async fn input(&self) -> OutputJson<&serde_json::Value> {
+        OutputJson(&self.model.input)
+    }
+
+    /// IDs of the nodes created or changed by the mutation
+    pub async fn node_ids(&self) -> Vec<ID> {
+        self.node_ids.iter().map(|id| (*id).into()).collect()
+    }
+
+    pub async fn request_id(&self) -> Option<&str> {
+        self.model.request_id.as_deref()
+    }
+
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.model.created_at
+    }
+
+    /// The person who executed the mutation, if it was not run by an
+    /// administrator from the command line
+    pub async fn actor(&self, context: &Context<'_>) -> FieldResult<Option<Person>> {
+        match self.model.actor_id {
+            Some(id) => Person::by_id(context.data::<Pool>().unwrap(), id)
+                .await
+                .map(Some)
+                .or_else(|_e| Err(FieldError::from("Internal error"))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn squad(&self, context: &Context<'_>) -> FieldResult<Option<Squad>> {
+        match self.model.squad_id {
+            Some(id) => Squad::by_id(context.data::<Pool>().unwrap(), id)
+                .await
+                .map(Some)
+                .or_else(|_e| Err(FieldError::from("Internal error"))),
+            None => Ok(None),
+        }
+    }
+}
@@ -2,10 +2,10 @@ use super::super::{nodes::Transaction, PageInfo};
 use crate::db::{
     models,
     schema::{node, txn, txn_part},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
 
 #[derive(async_graphql::SimpleObject)]
 pub struct BalanceTransactionEdge {
@@ -14,12 +14,23 @@ pub struct BalanceTransactionEdge {
     pub balance_change_cents: i32,
 }
 
-#[derive(async_graphql::SimpleObject)]
 pub struct BalanceTransactionConnection {
     pub edges: Vec<BalanceTransactionEdge>,
     pub page_info: PageInfo,
 }
 
+#[async_graphql::Object]
+impl BalanceTransactionConnection {
+    #[graphql(complexity = "super::BALANCE_TRANSACTIONS_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[BalanceTransactionEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
 impl BalanceTransactionConnection {
     pub async fn by_balance_id(
         pool: &Pool,
@@ -1,11 +1,26 @@
 mod balance_transaction;
 mod person_balance;
+mod person_search;
+mod squad_audit_log;
 mod squad_balance;
 mod squad_transaction;
 mod transaction_balance;
 
 pub use balance_transaction::*;
 pub use person_balance::*;
+pub use person_search::*;
+pub use squad_audit_log::*;
 pub use squad_balance::*;
 pub use squad_transaction::*;
 pub use transaction_balance::*;
+
+// Connections return every edge rather than a page of them, so the complexity
+// of a query is estimated by multiplying the complexity of each connection's
+// edges by how many of them it is likely to have
+pub const BALANCE_TRANSACTIONS_WEIGHT: usize = 50;
+pub const PERSON_BALANCES_WEIGHT: usize = 10;
+pub const SEARCH_PEOPLE_WEIGHT: usize = 20;
+pub const SQUAD_AUDIT_LOG_WEIGHT: usize = 50;
+pub const SQUAD_BALANCES_WEIGHT: usize = 20;
+pub const SQUAD_TRANSACTIONS_WEIGHT: usize = 50;
+pub const TRANSACTION_BALANCES_WEIGHT: usize = 10;
@@ -1,11 +1,11 @@
-use super::super::{nodes::Balance, PageInfo};
+use super::super::{nodes::Balance, PageInfo, Viewer};
 use crate::db::{
     models,
     schema::{balance, node},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
 
 #[derive(async_graphql::SimpleObject)]
 pub struct PersonBalanceEdge {
@@ -13,33 +13,62 @@ pub struct PersonBalanceEdge {
     pub node: Balance,
 }
 
-#[derive(async_graphql::SimpleObject)]
 pub struct PersonBalanceConnection {
     pub edges: Vec<PersonBalanceEdge>,
     pub page_info: PageInfo,
 }
 
+#[async_graphql::Object]
+impl PersonBalanceConnection {
+    #[graphql(complexity = "super::PERSON_BALANCES_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[PersonBalanceEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
 impl PersonBalanceConnection {
-    pub async fn by_person_id(pool: &Pool, person_id: i32) -> AsyncResult<PersonBalanceConnection> {
-        node::table
-            .inner_join(balance::table)
-            .filter(balance::person_id.eq(person_id))
-            .get_results_async::<models::Balance>(pool)
-            .await
-            .map(|results| PersonBalanceConnection {
-                edges: results
-                    .into_iter()
-                    .map(|balance| PersonBalanceEdge {
-                        cursor: String::from(""),
-                        node: balance.into(),
-                    })
-                    .collect(),
-                page_info: PageInfo {
-                    has_next_page: false,
-                    has_previous_page: false,
-                    start_cursor: String::from(""),
-                    end_cursor: String::from(""),
-                },
-            })
+    /// The person's balances in the squads the viewer is an active member
+    /// of, so that nobody can find their way to another squad's ledger
+    pub async fn by_person_id(
+        pool: &Pool,
+        viewer: &Viewer,
+        person_id: i32,
+    ) -> AsyncResult<PersonBalanceConnection> {
+        let squads = viewer
+            .squads(pool)
+            .await?
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        let results = if squads.is_empty() {
+            Vec::new()
+        } else {
+            node::table
+                .inner_join(balance::table)
+                .filter(balance::person_id.eq(person_id))
+                .filter(balance::squad_id.eq_any(squads))
+                .get_results_async::<models::Balance>(pool)
+                .await?
+        };
+
+        Ok(PersonBalanceConnection {
+            edges: results
+                .into_iter()
+                .map(|balance| PersonBalanceEdge {
+                    cursor: String::from(""),
+                    node: balance.into(),
+                })
+                .collect(),
+            page_info: PageInfo {
+                has_next_page: false,
+                has_previous_page: false,
+                start_cursor: String::from(""),
+                end_cursor: String::from(""),
+            },
+        })
     }
 }
@@ -0,0 +1,166 @@
+use super::super::{nodes::Person, PageInfo};
+use crate::db::{
+    models::{self, Visibility},
+    schema::{node, person},
+    traced::*,
+    Pool,
+};
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Float, Text},
+};
+
+sql_function!(fn lower(x: Text) -> Text);
+sql_function!(fn similarity(x: Text, y: Text) -> Float);
+
+#[derive(async_graphql::SimpleObject)]
+pub struct PersonSearchEdge {
+    pub cursor: String,
+    pub node: Person,
+}
+
+pub struct PersonSearchConnection {
+    pub edges: Vec<PersonSearchEdge>,
+    pub page_info: PageInfo,
+}
+
+#[async_graphql::Object]
+impl PersonSearchConnection {
+    #[graphql(complexity = "super::SEARCH_PEOPLE_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[PersonSearchEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
+/// The cursor of the result at the given offset
+fn encode_cursor(offset: i64) -> String {
+    base64::encode_config(format!("PersonSearch:{}", offset), base64::URL_SAFE_NO_PAD)
+}
+
+/// The offset of the result following the given cursor
+pub fn decode_cursor(cursor: &str) -> Option<i64> {
+    let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+    let offset = String::from_utf8(decoded).ok()?;
+    let offset = offset.strip_prefix("PersonSearch:")?.parse::<i64>().ok()?;
+    if offset >= 0 {
+        Some(offset + 1)
+    } else {
+        None
+    }
+}
+
+/// Escape the wildcards in a string to be matched literally by `LIKE`
+pub(super) fn escape_like(query: &str) -> String {
+    let mut escaped = String::with_capacity(query.len());
+    for c in query.chars() {
+        if let '\\' | '%' | '_' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl PersonSearchConnection {
+    /// Find the people whose display name or full name contains `query`, or
+    /// whose email address starts with it, best matches first. Only people who
+    /// share a squad with the signed-in person (identified by `viewer_id`),
+    /// i.e. are among their `squad_mates`, or who have chosen to be
+    /// discoverable, are found. Full names and email addresses are only
+    /// matched if the person's privacy settings let the viewer see them.
+    pub async fn search(
+        pool: &Pool,
+        viewer_id: i32,
+        squad_mates: Vec<i32>,
+        query: String,
+        first: i64,
+        offset: i64,
+    ) -> AsyncResult<PersonSearchConnection> {
+        // Matches the expression indexed by person_full_name_trgm
+        let full_name = || sql::<Text>("person.first_name || ' ' || person.last_name");
+        let contains = format!("%{}%", escape_like(&query));
+        let email_prefix = format!("{}%", escape_like(&query.to_lowercase()));
+
+        let name_visible = person::name_visibility
+            .eq(Visibility::Public)
+            .or(person::id.eq(viewer_id))
+            .or(person::name_visibility
+                .eq(Visibility::SquadMates)
+                .and(person::id.eq_any(squad_mates.clone())));
+        let email_visible = person::email_visibility
+            .eq(Visibility::Public)
+            .or(person::id.eq(viewer_id))
+            .or(person::email_visibility
+                .eq(Visibility::SquadMates)
+                .and(person::id.eq_any(squad_mates.clone())));
+
+        // People whose names are hidden from the viewer are ranked by their
+        // display names alone, so that the order doesn't give their names away
+        let results = node::table
+            .inner_join(person::table)
+            .filter(
+                person::display_name
+                    .ilike(contains.clone())
+                    .or(full_name().ilike(contains).and(name_visible))
+                    .or(lower(person::email).like(email_prefix).and(email_visible)),
+            )
+            .filter(
+                person::discoverable
+                    .or(person::id.eq(viewer_id))
+                    .or(person::id.eq_any(squad_mates)),
+            )
+            .order((similarity(person::display_name, query).desc(), person::id))
+            .offset(offset)
+            .limit(first + 1)
+            .get_results_async::<models::Person>(pool)
+            .await?;
+
+        let has_next_page = results.len() as i64 > first;
+        let edges = results
+            .into_iter()
+            .take(first as usize)
+            .zip(offset..)
+            .map(|(person, offset)| PersonSearchEdge {
+                cursor: encode_cursor(offset),
+                node: person.into(),
+            })
+            .collect::<Vec<_>>();
+
+        let cursor = |edge: Option<&PersonSearchEdge>| {
+            edge.map(|edge| edge.cursor.clone()).unwrap_or_default()
+        };
+        Ok(PersonSearchConnection {
+            page_info: PageInfo {
+                has_next_page,
+                has_previous_page: offset > 0,
+                start_cursor: cursor(edges.first()),
+                end_cursor: cursor(edges.last()),
+            },
+            edges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        assert_eq!(decode_cursor(&encode_cursor(0)), Some(1));
+        assert_eq!(decode_cursor(&encode_cursor(41)), Some(42));
+        assert_eq!(decode_cursor(&encode_cursor(-1)), None);
+        assert_eq!(decode_cursor("not a cursor"), None);
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("ann"), "ann");
+        assert_eq!(escape_like(r"100%_a\b"), r"100\%\_a\\b");
+    }
+}
@@ -0,0 +1,55 @@
+use super::super::{AuditLogEntry, PageInfo};
+use crate::db::{models, schema::audit_log, traced::*, Pool};
+use diesel::prelude::*;
+
+#[derive(async_graphql::SimpleObject)]
+pub struct SquadAuditLogEdge {
+    pub cursor: String,
+    pub node: AuditLogEntry,
+}
+
+pub struct SquadAuditLogConnection {
+    pub edges: Vec<SquadAuditLogEdge>,
+    pub page_info: PageInfo,
+}
+
+#[async_graphql::Object]
+impl SquadAuditLogConnection {
+    #[graphql(complexity = "super::SQUAD_AUDIT_LOG_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[SquadAuditLogEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
+impl SquadAuditLogConnection {
+    /// Audit log entries for the squad, most recent first
+    pub async fn by_squad_id(pool: &Pool, squad_id: i32) -> AsyncResult<SquadAuditLogConnection> {
+        let results = audit_log::table
+            .filter(audit_log::squad_id.eq(squad_id))
+            .order(audit_log::id.desc())
+            .get_results_async::<models::AuditLogEntry>(pool)
+            .await?;
+
+        AuditLogEntry::load_all(pool, results)
+            .await
+            .map(|entries| SquadAuditLogConnection {
+                edges: entries
+                    .into_iter()
+                    .map(|entry| SquadAuditLogEdge {
+                        cursor: String::from(""),
+                        node: entry,
+                    })
+                    .collect(),
+                page_info: PageInfo {
+                    has_next_page: false,
+                    has_previous_page: false,
+                    start_cursor: String::from(""),
+                    end_cursor: String::from(""),
+                },
+            })
+    }
+}
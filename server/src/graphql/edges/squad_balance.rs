@@ -2,10 +2,10 @@ use super::super::{nodes::Balance, PageInfo};
 use crate::db::{
     models,
     schema::{balance, node},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
 
 #[derive(async_graphql::SimpleObject)]
 pub struct SquadBalanceEdge {
@@ -13,12 +13,23 @@ pub struct SquadBalanceEdge {
     pub node: Balance,
 }
 
-#[derive(async_graphql::SimpleObject)]
 pub struct SquadBalanceConnection {
     pub edges: Vec<SquadBalanceEdge>,
     pub page_info: PageInfo,
 }
 
+#[async_graphql::Object]
+impl SquadBalanceConnection {
+    #[graphql(complexity = "super::SQUAD_BALANCES_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[SquadBalanceEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
 impl SquadBalanceConnection {
     pub async fn by_squad_id(pool: &Pool, squad_id: i32) -> AsyncResult<SquadBalanceConnection> {
         node::table
@@ -1,11 +1,23 @@
-use super::super::{nodes::Transaction, PageInfo};
+use super::super::{nodes::Transaction, IdParser, PageInfo};
+use super::person_search::escape_like;
 use crate::db::{
     models,
-    schema::{node, txn},
+    schema::{balance, node, person, txn, txn_part},
+    traced::{AsyncConnection, AsyncResult, TracedConnection},
     Pool,
 };
-use diesel::prelude::*;
-use tokio_diesel::*;
+use async_graphql::{
+    validators::{StringMaxLength, StringMinLength},
+    FieldResult, ID,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Bool, Integer, Timestamptz},
+};
+use std::convert::TryFrom;
+use uuid::Uuid;
 
 #[derive(async_graphql::SimpleObject)]
 pub struct SquadTransactionEdge {
@@ -13,36 +25,387 @@ pub struct SquadTransactionEdge {
     pub node: Transaction,
 }
 
-#[derive(async_graphql::SimpleObject)]
 pub struct SquadTransactionConnection {
     pub edges: Vec<SquadTransactionEdge>,
     pub page_info: PageInfo,
 }
 
+#[async_graphql::Object]
 impl SquadTransactionConnection {
+    #[graphql(complexity = "super::SQUAD_TRANSACTIONS_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[SquadTransactionEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
+/// Which of a squad's transactions to return. Every condition given must hold.
+#[derive(async_graphql::InputObject)]
+pub struct TransactionFilter {
+    /// Only transactions which happened at or after this time
+    pub occurred_after: Option<DateTime<Utc>>,
+    /// Only transactions which happened before this time
+    pub occurred_before: Option<DateTime<Utc>>,
+    /// Only transactions which changed this person's balance
+    pub person_id: Option<ID>,
+    pub min_amount_cents: Option<i32>,
+    pub max_amount_cents: Option<i32>,
+    pub kind: Option<models::TransactionKind>,
+    /// Only transactions whose description contains this text
+    #[graphql(validator(and(StringMinLength(length = "2"), StringMaxLength(length = "100"))))]
+    pub search: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ParsedTransactionFilter {
+    occurred_after: Option<DateTime<Utc>>,
+    occurred_before: Option<DateTime<Utc>>,
+    person_uid: Option<Uuid>,
+    min_amount_cents: Option<i32>,
+    max_amount_cents: Option<i32>,
+    kind: Option<models::TransactionKind>,
+    search: Option<String>,
+}
+
+impl ParsedTransactionFilter {
+    pub fn parse(value: TransactionFilter, ids: &IdParser) -> FieldResult<ParsedTransactionFilter> {
+        let person_uid = match value.person_id {
+            Some(person_id) => Some(ids.parse(&person_id, models::NodeType::Person)?),
+            None => None,
+        };
+
+        Ok(ParsedTransactionFilter {
+            occurred_after: value.occurred_after,
+            occurred_before: value.occurred_before,
+            person_uid,
+            min_amount_cents: value.min_amount_cents,
+            max_amount_cents: value.max_amount_cents,
+            kind: value.kind,
+            search: value.search,
+        })
+    }
+}
+
+/// What a squad's transactions are sorted by. Transactions which are equal
+/// are sorted in the order they were recorded.
+#[derive(async_graphql::Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransactionOrderField {
+    OccurredAt,
+    CreatedAt,
+    Amount,
+}
+
+#[derive(async_graphql::Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OrderDirection {
+    Asc,
+    Desc,
+}
+
+impl TransactionOrderField {
+    fn name(self) -> &'static str {
+        match self {
+            TransactionOrderField::OccurredAt => "occurred_at",
+            TransactionOrderField::CreatedAt => "created_at",
+            TransactionOrderField::Amount => "amount_cents",
+        }
+    }
+}
+
+/// Times are encoded in cursors in microseconds, the precision the database
+/// stores them with
+fn micros(time: DateTime<Utc>) -> i64 {
+    time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros())
+}
+
+fn from_micros(micros: i64) -> Option<DateTime<Utc>> {
+    let nanos = u32::try_from(micros.rem_euclid(1_000_000) * 1_000).ok()?;
+    Utc.timestamp_opt(micros.div_euclid(1_000_000), nanos)
+        .single()
+}
+
+#[derive(Debug, PartialEq)]
+enum CursorValue {
+    Time(DateTime<Utc>),
+    Cents(i32),
+}
+
+/// The position of a transaction in a sorted list: the value of the field
+/// sorted by, and the transaction's ID to break ties
+#[derive(Debug, PartialEq)]
+pub struct TransactionCursor {
+    order_by: TransactionOrderField,
+    value: CursorValue,
+    txn_id: i32,
+}
+
+impl TransactionCursor {
+    fn new(order_by: TransactionOrderField, transaction: &models::TransactionDetail) -> Self {
+        let value = match order_by {
+            TransactionOrderField::OccurredAt => CursorValue::Time(transaction.occurred_at),
+            TransactionOrderField::CreatedAt => CursorValue::Time(transaction.created_at),
+            TransactionOrderField::Amount => CursorValue::Cents(transaction.amount_cents),
+        };
+
+        TransactionCursor {
+            order_by,
+            value,
+            txn_id: transaction.id,
+        }
+    }
+
+    fn encode(&self) -> String {
+        let value = match self.value {
+            CursorValue::Time(time) => micros(time),
+            CursorValue::Cents(cents) => cents.into(),
+        };
+        let cursor = format!(
+            "Transaction:{}:{}:{}",
+            self.order_by.name(),
+            value,
+            self.txn_id
+        );
+        base64::encode_config(cursor, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decode a cursor, which must have come from a list sorted by `order_by`
+    pub fn decode(cursor: &str, order_by: TransactionOrderField) -> Option<Self> {
+        let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let mut parts = decoded.splitn(4, ':');
+        if parts.next()? != "Transaction" || parts.next()? != order_by.name() {
+            return None;
+        }
+
+        let value = parts.next()?;
+        let value = match order_by {
+            TransactionOrderField::Amount => CursorValue::Cents(value.parse().ok()?),
+            _ => CursorValue::Time(from_micros(value.parse().ok()?)?),
+        };
+
+        Some(TransactionCursor {
+            order_by,
+            value,
+            txn_id: parts.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Load the squad's transactions which match `filter`, in order, following the
+/// one at `after`. Boxed queries can't be sent to another thread, so the query
+/// is built on the one it runs on.
+fn load(
+    conn: &TracedConnection,
+    squad_id: i32,
+    filter: ParsedTransactionFilter,
+    order_by: TransactionOrderField,
+    direction: OrderDirection,
+    limit: Option<i64>,
+    after: Option<TransactionCursor>,
+) -> QueryResult<Vec<models::Transaction>> {
+    // Look the person up first, since the query below can't refer to the node
+    // table twice
+    let person_id = match filter.person_uid {
+        Some(person_uid) => {
+            let person_id = node::table
+                .inner_join(person::table)
+                .filter(node::uid.eq(person_uid))
+                .select(person::id)
+                .get_result::<i32>(conn)
+                .optional()?;
+            match person_id {
+                Some(person_id) => Some(person_id),
+                None => return Ok(Vec::new()),
+            }
+        }
+        None => None,
+    };
+
+    let mut query = node::table
+        .inner_join(txn::table)
+        .filter(txn::squad_id.eq(squad_id))
+        .into_boxed();
+
+    if let Some(occurred_after) = filter.occurred_after {
+        query = query.filter(txn::occurred_at.ge(occurred_after));
+    }
+    if let Some(occurred_before) = filter.occurred_before {
+        query = query.filter(txn::occurred_at.lt(occurred_before));
+    }
+    if let Some(person_id) = person_id {
+        query = query.filter(
+            txn::id.eq_any(
+                txn_part::table
+                    .inner_join(balance::table)
+                    .filter(balance::person_id.eq(person_id))
+                    .select(txn_part::txn_id),
+            ),
+        );
+    }
+    if let Some(min_amount_cents) = filter.min_amount_cents {
+        query = query.filter(txn::amount_cents.ge(min_amount_cents));
+    }
+    if let Some(max_amount_cents) = filter.max_amount_cents {
+        query = query.filter(txn::amount_cents.le(max_amount_cents));
+    }
+    if let Some(kind) = filter.kind {
+        query = query.filter(txn::kind.eq(kind));
+    }
+    if let Some(search) = &filter.search {
+        query = query.filter(txn::description.ilike(format!("%{}%", escape_like(search))));
+    }
+
+    // Compare (value, id) pairs as rows, which lets the database use the
+    // indexes on (squad_id, <field>, id) to find where the page starts
+    if let Some(after) = after {
+        let condition = sql::<Bool>(&format!(
+            "(txn.{}, txn.id) {} (",
+            order_by.name(),
+            if direction == OrderDirection::Asc {
+                ">"
+            } else {
+                "<"
+            },
+        ));
+        query = match after.value {
+            CursorValue::Time(time) => query.filter(
+                condition
+                    .bind::<Timestamptz, _>(time)
+                    .sql(", ")
+                    .bind::<Integer, _>(after.txn_id)
+                    .sql(")"),
+            ),
+            CursorValue::Cents(cents) => query.filter(
+                condition
+                    .bind::<Integer, _>(cents)
+                    .sql(", ")
+                    .bind::<Integer, _>(after.txn_id)
+                    .sql(")"),
+            ),
+        };
+    }
+
+    query = match (order_by, direction) {
+        (TransactionOrderField::OccurredAt, OrderDirection::Asc) => {
+            query.order((txn::occurred_at.asc(), txn::id.asc()))
+        }
+        (TransactionOrderField::OccurredAt, OrderDirection::Desc) => {
+            query.order((txn::occurred_at.desc(), txn::id.desc()))
+        }
+        (TransactionOrderField::CreatedAt, OrderDirection::Asc) => {
+            query.order((txn::created_at.asc(), txn::id.asc()))
+        }
+        (TransactionOrderField::CreatedAt, OrderDirection::Desc) => {
+            query.order((txn::created_at.desc(), txn::id.desc()))
+        }
+        (TransactionOrderField::Amount, OrderDirection::Asc) => {
+            query.order((txn::amount_cents.asc(), txn::id.asc()))
+        }
+        (TransactionOrderField::Amount, OrderDirection::Desc) => {
+            query.order((txn::amount_cents.desc(), txn::id.desc()))
+        }
+    };
+
+    if let Some(limit) = limit {
+        query = query.limit(limit);
+    }
+
+    query.get_results::<models::Transaction>(conn)
+}
+
+impl SquadTransactionConnection {
+    /// The squad's transactions which match `filter`, sorted by `order_by`.
+    /// Returns the first `first` of them, or all of them, following the one
+    /// at `after`.
     pub async fn by_squad_id(
         pool: &Pool,
         squad_id: i32,
+        filter: ParsedTransactionFilter,
+        order_by: TransactionOrderField,
+        direction: OrderDirection,
+        first: Option<i64>,
+        after: Option<TransactionCursor>,
     ) -> AsyncResult<SquadTransactionConnection> {
-        node::table
-            .inner_join(txn::table)
-            .filter(txn::squad_id.eq(squad_id))
-            .get_results_async::<models::Transaction>(pool)
-            .await
-            .map(|results| SquadTransactionConnection {
-                edges: results
-                    .into_iter()
-                    .map(|transaction| SquadTransactionEdge {
-                        cursor: String::from(""),
-                        node: transaction.into(),
-                    })
-                    .collect(),
-                page_info: PageInfo {
-                    has_next_page: false,
-                    has_previous_page: false,
-                    start_cursor: String::from(""),
-                    end_cursor: String::from(""),
-                },
+        // Fetch one more than asked for, to find out whether there are more
+        let limit = first.map(|first| first + 1);
+        let mut results = pool
+            .transaction(move |conn| {
+                load(conn, squad_id, filter, order_by, direction, limit, after)
             })
+            .await?;
+
+        let has_next_page = match first {
+            Some(first) if results.len() as i64 > first => {
+                results.truncate(first as usize);
+                true
+            }
+            _ => false,
+        };
+
+        let edges = results
+            .into_iter()
+            .map(|transaction| SquadTransactionEdge {
+                cursor: TransactionCursor::new(order_by, &transaction.detail).encode(),
+                node: transaction.into(),
+            })
+            .collect::<Vec<_>>();
+
+        let cursor = |edge: Option<&SquadTransactionEdge>| {
+            edge.map(|edge| edge.cursor.clone()).unwrap_or_default()
+        };
+        Ok(SquadTransactionConnection {
+            page_info: PageInfo {
+                has_next_page,
+                has_previous_page: false,
+                start_cursor: cursor(edges.first()),
+                end_cursor: cursor(edges.last()),
+            },
+            edges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let occurred_at = Utc.ymd(2021, 1, 16).and_hms_micro(21, 5, 31, 123_456);
+        let cursor = TransactionCursor {
+            order_by: TransactionOrderField::OccurredAt,
+            value: CursorValue::Time(occurred_at),
+            txn_id: 42,
+        };
+        assert_eq!(
+            TransactionCursor::decode(&cursor.encode(), TransactionOrderField::OccurredAt),
+            Some(cursor)
+        );
+
+        // Times before 1970 round-trip too
+        let early = Utc.ymd(1969, 12, 31).and_hms_micro(23, 59, 59, 500_000);
+        assert_eq!(from_micros(micros(early)), Some(early));
+
+        // Cursors are only valid for the order they came from
+        let cursor = TransactionCursor {
+            order_by: TransactionOrderField::Amount,
+            value: CursorValue::Cents(-1500),
+            txn_id: 7,
+        };
+        let encoded = cursor.encode();
+        assert_eq!(
+            TransactionCursor::decode(&encoded, TransactionOrderField::Amount),
+            Some(cursor)
+        );
+        assert_eq!(
+            TransactionCursor::decode(&encoded, TransactionOrderField::CreatedAt),
+            None
+        );
+        assert_eq!(
+            TransactionCursor::decode("not a cursor", TransactionOrderField::Amount),
+            None
+        );
     }
 }
@@ -2,10 +2,10 @@ use super::super::{nodes::Balance, PageInfo};
 use crate::db::{
     models,
     schema::{balance, node, txn_part},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
 
 #[derive(async_graphql::SimpleObject)]
 pub struct TransactionBalanceEdge {
@@ -14,12 +14,23 @@ pub struct TransactionBalanceEdge {
     pub balance_change_cents: i32,
 }
 
-#[derive(async_graphql::SimpleObject)]
 pub struct TransactionBalanceConnection {
     pub edges: Vec<TransactionBalanceEdge>,
     pub page_info: PageInfo,
 }
 
+#[async_graphql::Object]
+impl TransactionBalanceConnection {
+    #[graphql(complexity = "super::TRANSACTION_BALANCES_WEIGHT * child_complexity")]
+    pub async fn edges(&self) -> &[TransactionBalanceEdge] {
+        &self.edges
+    }
+
+    pub async fn page_info(&self) -> &PageInfo {
+        &self.page_info
+    }
+}
+
 impl TransactionBalanceConnection {
     pub async fn by_transaction_id(
         pool: &Pool,
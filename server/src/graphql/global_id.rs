@@ -0,0 +1,141 @@
+//! Relay global IDs. Clients see the ID of every node as an opaque string,
+//! the base64 encoding of its type and UUID (e.g. `Squad:<uuid>`), so that the
+//! type of node an ID refers to can be checked, or looked up, without a
+//! database query.
+
+use super::GraphQLSettings;
+use crate::db::models::{self, NodeType};
+use async_graphql::{FieldError, FieldResult};
+use std::fmt;
+use uuid::Uuid;
+
+/// The type and UUID of a node
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalId {
+    pub node_type: NodeType,
+    pub uid: Uuid,
+}
+
+fn type_name(node_type: NodeType) -> &'static str {
+    match node_type {
+        NodeType::Person => "Person",
+        NodeType::Squad => "Squad",
+        NodeType::Balance => "Balance",
+        NodeType::Txn => "Transaction",
+    }
+}
+
+impl GlobalId {
+    pub fn new(node_type: NodeType, uid: Uuid) -> GlobalId {
+        GlobalId { node_type, uid }
+    }
+
+    fn decode(id: &str, accept_raw_uuids: bool) -> Option<(Option<NodeType>, Uuid)> {
+        if let Some(global_id) = GlobalId::decode_global(id) {
+            return Some((Some(global_id.node_type), global_id.uid));
+        }
+
+        if accept_raw_uuids {
+            return Uuid::parse_str(id).ok().map(|uid| (None, uid));
+        }
+
+        None
+    }
+
+    fn decode_global(id: &str) -> Option<GlobalId> {
+        let decoded = base64::decode_config(id, base64::URL_SAFE_NO_PAD).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let mut parts = decoded.splitn(2, ':');
+        let (name, uid) = (parts.next()?, parts.next()?);
+
+        let node_type = [
+            NodeType::Person,
+            NodeType::Squad,
+            NodeType::Balance,
+            NodeType::Txn,
+        ]
+        .iter()
+        .copied()
+        .find(|&node_type| type_name(node_type) == name)?;
+
+        Some(GlobalId::new(node_type, Uuid::parse_str(uid).ok()?))
+    }
+}
+
+/// Parses the IDs sent by clients. Whether bare UUIDs, the IDs used before
+/// global IDs, are still accepted in their place is configured by
+/// `graphql.accept_raw_uuids`, so the parser is part of the schema's data.
+#[derive(Clone, Copy, Debug)]
+pub struct IdParser {
+    accept_raw_uuids: bool,
+}
+
+impl IdParser {
+    pub fn new(settings: &GraphQLSettings) -> IdParser {
+        IdParser {
+            accept_raw_uuids: settings.accept_raw_uuids,
+        }
+    }
+
+    /// Parse the ID of a node, which must be of the given type
+    pub fn parse(&self, id: &str, node_type: NodeType) -> FieldResult<Uuid> {
+        match self.parse_any(id)? {
+            (Some(actual), uid) if actual == node_type => Ok(uid),
+            (None, uid) => Ok(uid),
+            _ => Err(FieldError::from(format!(
+                "Invalid ID: expected the ID of a {}",
+                type_name(node_type)
+            ))),
+        }
+    }
+
+    /// Parse the ID of a node of any type. The type is unknown if the ID is a
+    /// bare UUID.
+    pub fn parse_any(&self, id: &str) -> FieldResult<(Option<NodeType>, Uuid)> {
+        GlobalId::decode(id, self.accept_raw_uuids).ok_or_else(|| FieldError::from("Invalid ID"))
+    }
+}
+
+impl From<&models::Node> for GlobalId {
+    fn from(node: &models::Node) -> Self {
+        GlobalId::new(node.node_type, node.uid)
+    }
+}
+
+impl fmt::Display for GlobalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = format!("{}:{}", type_name(self.node_type), self.uid);
+        f.write_str(&base64::encode_config(id, base64::URL_SAFE_NO_PAD))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let uid = Uuid::new_v4();
+        let id = GlobalId::new(NodeType::Txn, uid).to_string();
+        assert_eq!(
+            GlobalId::decode(&id, false),
+            Some((Some(NodeType::Txn), uid))
+        );
+
+        // Raw UUIDs are only accepted if enabled
+        let raw = uid.to_string();
+        assert_eq!(GlobalId::decode(&raw, true), Some((None, uid)));
+        assert_eq!(GlobalId::decode(&raw, false), None);
+
+        let encode = |id: &str| base64::encode_config(id, base64::URL_SAFE_NO_PAD);
+        for invalid in &[
+            String::new(),
+            "not an ID".to_string(),
+            encode("Squad"),
+            encode(&format!("Txn:{}", uid)),
+            encode("Squad:not-a-uuid"),
+        ] {
+            assert_eq!(GlobalId::decode(invalid, true), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::nodes::{Person, Squad};
+use crate::db::{
+    models,
+    schema::{balance, invite, squad},
+    traced::*,
+    Pool,
+};
+use crate::settings::Settings;
+use async_graphql::{Context, FieldError, FieldResult};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+
+/// An invitation to join a squad, identified by a secret token
+pub struct Invite {
+    pub model: models::Invite,
+}
+
+impl From<models::Invite> for Invite {
+    fn from(model: models::Invite) -> Self {
+        Invite { model }
+    }
+}
+
+#[async_graphql::Object]
+impl Invite {
+    pub async fn token(&self) -> &str {
+        &self.model.token
+    }
+
+    /// Link which can be shared with the people being invited
+    pub async fn url(&self, context: &Context<'_>) -> String {
+        Invite::url_for(
+            &context.data::<Settings>().unwrap().server.name,
+            &self.model.token,
+        )
+    }
+
+    /// The only email which may accept the invite, if it is restricted
+    pub async fn email(&self) -> Option<&str> {
+        self.model.email.as_deref()
+    }
+
+    pub async fn expires_at(&self) -> DateTime<Utc> {
+        self.model.expires_at
+    }
+
+    pub async fn max_uses(&self) -> Option<i32> {
+        self.model.max_uses
+    }
+
+    pub async fn use_count(&self) -> i32 {
+        self.model.use_count
+    }
+
+    pub async fn squad(&self, context: &Context<'_>) -> FieldResult<Squad> {
+        Squad::by_id(context.data::<Pool>().unwrap(), self.model.squad_id)
+            .await
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+
+    pub async fn created_by(&self, context: &Context<'_>) -> FieldResult<Person> {
+        Person::by_id(context.data::<Pool>().unwrap(), self.model.created_by)
+            .await
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+}
+
+impl Invite {
+    /// Link to the invite identified by `token` on the server named
+    /// `server_name`
+    pub fn url_for(server_name: &str, token: &str) -> String {
+        format!("https://{}/?invite={}", server_name, token)
+    }
+
+    /// Invites addressed to the email of the person identified by
+    /// `person_id` which they may still accept, i.e. not to squads they are
+    /// already an active member of
+    pub async fn pending_for_email(
+        pool: &Pool,
+        person_id: i32,
+        email: String,
+    ) -> AsyncResult<Vec<Invite>> {
+        let now = Utc::now();
+        let member_of = balance::table
+            .select(balance::squad_id)
+            .filter(balance::person_id.eq(person_id))
+            .filter(balance::active);
+
+        invite::table
+            .inner_join(squad::table)
+            .filter(invite::email.eq(email.to_lowercase()))
+            .filter(diesel::dsl::not(invite::squad_id.eq_any(member_of)))
+            .filter(invite::expires_at.gt(now))
+            .filter(squad::archived.eq(false))
+            .select(invite::all_columns)
+            .order(invite::id)
+            .get_results_async::<models::Invite>(pool)
+            .await
+            .map(|results| {
+                results
+                    .into_iter()
+                    .filter(|invite| invite.is_usable(now))
+                    .map(|invite| invite.into())
+                    .collect()
+            })
+    }
+}
@@ -2,24 +2,69 @@ pub mod edges;
 pub mod mutations;
 pub mod nodes;
 
+mod audit_log;
+mod global_id;
+mod invite;
 mod mutation_root;
 mod page_info;
+mod persisted_queries;
 mod query_root;
+mod viewer;
 
+pub use audit_log::*;
+pub use global_id::{GlobalId, IdParser};
+pub use invite::*;
 pub use mutation_root::*;
 pub use page_info::*;
+pub use persisted_queries::{PersistedQueries, PersistedQuerySettings};
 pub use query_root::*;
+pub use viewer::*;
 
-use crate::{db, settings::Settings};
-use async_graphql::EmptySubscription;
+use crate::{db, mail::Mailer, metrics::Metrics, settings::Settings};
+use anyhow::{anyhow, Result};
+use async_graphql::{EmptySubscription, SchemaBuilder};
+use serde::Deserialize;
+
+/// Config parameters pertaining to GraphQL execution
+#[derive(Clone, Deserialize)]
+pub struct GraphQLSettings {
+    pub max_depth: usize,
+    pub max_complexity: usize,
+    pub accept_raw_uuids: bool,
+}
 
 pub type Schema = async_graphql::Schema<QueryRoot, MutationRoot, EmptySubscription>;
 
-pub fn make_schema(settings: Settings, pool: db::Pool) -> Schema {
-    let mut builder = Schema::build(QueryRoot {}, MutationRoot {}, EmptySubscription {})
+/// Build the schema without any of the data needed to execute requests, which
+/// is enough to describe it (e.g. with `Schema::sdl`)
+pub fn schema_builder() -> SchemaBuilder<QueryRoot, MutationRoot, EmptySubscription> {
+    Schema::build(QueryRoot {}, MutationRoot {}, EmptySubscription {})
+}
+
+pub fn make_schema(
+    settings: Settings,
+    pool: db::Pool,
+    metrics: Metrics,
+    persisted_queries: PersistedQueries,
+    mailer: Mailer,
+) -> Schema {
+    let mut builder = with_limits(schema_builder(), &settings.graphql);
+
+    // Persisted queries must be resolved before anything else sees the query
+    builder = match persisted_queries {
+        PersistedQueries::Off => builder,
+        PersistedQueries::Apq(store) => builder.extension(store),
+        PersistedQueries::Allowlist(allowlist) => builder.extension(allowlist),
+    };
+
+    builder = builder
+        .data(IdParser::new(&settings.graphql))
         .data(settings)
         .data(pool)
-        .extension(async_graphql::extensions::Logger);
+        .data(mailer)
+        .extension(async_graphql::extensions::Logger)
+        .extension(async_graphql::extensions::Tracing)
+        .extension(metrics.graphql());
 
     #[cfg(feature = "graphiql")]
     {
@@ -33,3 +78,121 @@ pub fn make_schema(settings: Settings, pool: db::Pool) -> Schema {
 
     builder.finish()
 }
+
+/// Reject queries which would fan out across the graph before executing any of
+/// them
+fn with_limits(
+    mut builder: SchemaBuilder<QueryRoot, MutationRoot, EmptySubscription>,
+    settings: &GraphQLSettings,
+) -> SchemaBuilder<QueryRoot, MutationRoot, EmptySubscription> {
+    if settings.max_depth > 0 {
+        builder = builder.limit_depth(settings.max_depth);
+    }
+    if settings.max_complexity > 0 {
+        builder = builder.limit_complexity(settings.max_complexity);
+    }
+    builder
+}
+
+/// The schema in GraphQL SDL, as committed in `schema.graphql`
+pub fn export_sdl() -> String {
+    schema_builder().finish().sdl()
+}
+
+/// The result of the standard introspection query against the schema, as
+/// committed in `schema.json`
+pub async fn export_introspection() -> Result<serde_json::Value> {
+    let response = schema_builder()
+        .finish()
+        .execute(include_str!("introspection.graphql"))
+        .await;
+
+    if let Some(error) = response.errors.first() {
+        return Err(anyhow!("Failed to introspect schema: {}", error.message));
+    }
+
+    Ok(serde_json::json!({ "data": response.data.into_json()? }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_introspection, export_sdl, schema_builder, with_limits};
+    use crate::settings::Settings;
+    use std::{fs, path::Path};
+
+    const EXPORT_COMMAND: &str =
+        "cargo run -- export-schema --sdl schema.graphql --json schema.json";
+
+    fn read_committed(file_name: &str) -> String {
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(file_name)).unwrap()
+    }
+
+    #[test]
+    fn test_committed_sdl_is_current() {
+        assert!(
+            read_committed("schema.graphql") == export_sdl(),
+            "schema.graphql is out of date. Regenerate it with `{}`",
+            EXPORT_COMMAND
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_committed_introspection_is_current() {
+        let committed = serde_json::from_str::<serde_json::Value>(&read_committed("schema.json"));
+
+        assert!(
+            committed.unwrap() == export_introspection().await.unwrap(),
+            "schema.json is out of date. Regenerate it with `{}`",
+            EXPORT_COMMAND
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_default_limits() {
+        let settings = Settings::init(None).unwrap();
+        let schema = with_limits(schema_builder(), &settings.graphql).finish();
+
+        // Tools such as graphiql must still be able to introspect the schema
+        let response = schema.execute(include_str!("introspection.graphql")).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        // Listing the viewer's squads with their transactions is allowed
+        let response = schema
+            .execute(
+                "{ viewer { balances { edges { node { squad {
+                    displayName
+                    transactions { edges { node { id balances { edges { node {
+                        totalCents person { displayName }
+                    } } } } } }
+                } } } } } }",
+            )
+            .await;
+        // (failing only once executed, for lack of a viewer)
+        assert!(
+            response.errors.iter().all(|error| !error.path.is_empty()),
+            "{:?}",
+            response.errors
+        );
+
+        // Rather than fanning out across the graph
+        let response = schema
+            .execute(
+                "{ viewer { balances { edges { node { squad { balances { edges { node {
+                    transactions { edges { node { balances { edges { node { id } } } } } }
+                } } } } } } } } }",
+            )
+            .await;
+        assert_eq!(response.errors[0].message, "Query is too complex.");
+
+        let response = schema
+            .execute(
+                "{ viewer { balances { edges { node { person { balances { edges { node {
+                    person { balances { edges { node { person { balances { edges { node {
+                        squad { id }
+                    } } } } } } } } }
+                } } } } } } } }",
+            )
+            .await;
+        assert_eq!(response.errors[0].message, "Query is nested too deep.");
+    }
+}
@@ -1,7 +1,8 @@
-use super::mutations::*;
-use crate::db::Pool;
-use async_graphql::{Context, FieldError, FieldResult};
-use std::convert::TryInto;
+use super::{mutations::*, IdParser, Viewer};
+use crate::db::{traced::AsyncError, Pool};
+use crate::{mail::Mailer, settings::Settings};
+use async_graphql::{Context, ErrorExtensions, FieldError, FieldResult};
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
 
 /// Schema entry-point for mutations
 pub struct MutationRoot;
@@ -13,7 +14,9 @@ impl MutationRoot {
         context: &Context<'_>,
         input: NewPersonInput,
     ) -> FieldResult<NewPersonPayload> {
-        new_person(context.data::<Pool>().unwrap(), input)
+        let audit = Audit::from_context(context, "newPerson", &input);
+
+        new_person(context.data::<Pool>().unwrap(), input, audit)
             .await
             .or_else(|_e| {
                 // TODO: provide feedback on duplicate email or display_name
@@ -26,9 +29,17 @@ impl MutationRoot {
         context: &Context<'_>,
         input: NewSquadInput,
     ) -> FieldResult<NewSquadPayload> {
-        new_squad(context.data::<Pool>().unwrap(), input)
-            .await
-            .or_else(|_e| Err(FieldError::from("Failed to create new squad")))
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "newSquad", &input);
+
+        new_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            input,
+            audit,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Failed to create new squad")))
     }
 
     async fn add_person_to_squad(
@@ -36,9 +47,17 @@ impl MutationRoot {
         context: &Context<'_>,
         input: AddPersonToSquadInput,
     ) -> FieldResult<AddPersonToSquadPayload> {
-        add_person_to_squad(context.data::<Pool>().unwrap(), input.try_into()?)
-            .await
-            .or_else(|_e| Err(FieldError::from("Failed to add person to squad")))
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "addPersonToSquad", &input);
+
+        add_person_to_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedAddPersonToSquadInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Failed to add person to squad")))
     }
 
     async fn new_transaction(
@@ -46,8 +65,230 @@ impl MutationRoot {
         context: &Context<'_>,
         input: NewTransactionInput,
     ) -> FieldResult<NewTransactionPayload> {
-        new_transaction(context.data::<Pool>().unwrap(), input.try_into()?)
-            .await
-            .or_else(|_e| Err(FieldError::from("Failed to add transaction")))
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "newTransaction", &input);
+
+        new_transaction(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedNewTransactionInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|e| match e.downcast_ref::<KeyReused>() {
+            Some(key_reused) => Err(key_reused.extend()),
+            None => Err(FieldError::from("Failed to add transaction")),
+        })
+    }
+
+    async fn update_person(
+        &self,
+        context: &Context<'_>,
+        input: UpdatePersonInput,
+    ) -> FieldResult<UpdatePersonPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "updatePerson", &input);
+
+        update_person(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedUpdatePersonInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|e| {
+            if let Some(conflict) = e.downcast_ref::<Conflict>() {
+                return Err(conflict.extend());
+            }
+            // An email collision gets the generic error, so that this can't be
+            // used to find out who has an account
+            match e.downcast_ref::<AsyncError>() {
+                Some(AsyncError::Error(DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    info,
+                ))) if info.constraint_name() == Some("person_display_name_key") => {
+                    Err(FieldError::from("Display name is already in use"))
+                }
+                _ => Err(FieldError::from("Failed to update person")),
+            }
+        })
+    }
+
+    async fn update_squad(
+        &self,
+        context: &Context<'_>,
+        input: UpdateSquadInput,
+    ) -> FieldResult<UpdateSquadPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "updateSquad", &input);
+
+        update_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedUpdateSquadInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|e| match e.downcast_ref::<Conflict>() {
+            Some(conflict) => Err(conflict.extend()),
+            None => Err(FieldError::from("Failed to update squad")),
+        })
+    }
+
+    async fn archive_squad(
+        &self,
+        context: &Context<'_>,
+        input: ArchiveSquadInput,
+    ) -> FieldResult<ArchiveSquadPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "archiveSquad", &input);
+
+        archive_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedArchiveSquadInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|e| match e.downcast_ref::<Conflict>() {
+            Some(conflict) => Err(conflict.extend()),
+            None => Err(FieldError::from("Failed to archive squad")),
+        })
+    }
+
+    async fn remove_person_from_squad(
+        &self,
+        context: &Context<'_>,
+        input: RemovePersonFromSquadInput,
+    ) -> FieldResult<RemovePersonFromSquadPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "removePersonFromSquad", &input);
+
+        remove_person_from_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedRemovePersonFromSquadInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|_e| {
+            Err(FieldError::from(
+                "Failed to remove person from squad. Their balance must be settled first",
+            ))
+        })
+    }
+
+    async fn leave_squad(
+        &self,
+        context: &Context<'_>,
+        input: LeaveSquadInput,
+    ) -> FieldResult<LeaveSquadPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "leaveSquad", &input);
+
+        leave_squad(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedLeaveSquadInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|_e| {
+            Err(FieldError::from(
+                "Failed to leave squad. Your balance must be settled first",
+            ))
+        })
+    }
+
+    async fn create_squad_invite(
+        &self,
+        context: &Context<'_>,
+        input: CreateSquadInviteInput,
+    ) -> FieldResult<CreateSquadInvitePayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "createSquadInvite", &input);
+
+        create_squad_invite(
+            context.data::<Pool>().unwrap(),
+            context.data::<Mailer>()?,
+            &context.data::<Settings>()?.server.name,
+            viewer.detail.id,
+            ParsedCreateSquadInviteInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Failed to create invite")))
+    }
+
+    async fn accept_invite(
+        &self,
+        context: &Context<'_>,
+        input: AcceptInviteInput,
+    ) -> FieldResult<AcceptInvitePayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "acceptInvite", &input).redact("token");
+
+        accept_invite(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            viewer.detail.email.clone(),
+            input,
+            audit,
+        )
+        .await
+        .or_else(|e| match e.downcast_ref::<AlreadyMember>() {
+            Some(already_member) => Err(already_member.extend()),
+            None => Err(FieldError::from(
+                "Invite is invalid, expired, or has already been used",
+            )),
+        })
+    }
+
+    async fn void_transaction(
+        &self,
+        context: &Context<'_>,
+        input: VoidTransactionInput,
+    ) -> FieldResult<VoidTransactionPayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "voidTransaction", &input);
+
+        void_transaction(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedVoidTransactionInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|e| {
+            if let Some(key_reused) = e.downcast_ref::<KeyReused>() {
+                return Err(key_reused.extend());
+            }
+            match e.downcast_ref::<Conflict>() {
+                Some(conflict) => Err(conflict.extend()),
+                None => Err(FieldError::from("Failed to void transaction")),
+            }
+        })
+    }
+
+    async fn set_member_role(
+        &self,
+        context: &Context<'_>,
+        input: SetMemberRoleInput,
+    ) -> FieldResult<SetMemberRolePayload> {
+        let viewer = context.data::<Viewer>()?.person()?;
+        let audit = Audit::from_context(context, "setMemberRole", &input);
+
+        set_member_role(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            ParsedSetMemberRoleInput::parse(input, context.data::<IdParser>()?)?,
+            audit,
+        )
+        .await
+        .or_else(|_e| {
+            Err(FieldError::from(
+                "Failed to change role. A squad must always have an owner",
+            ))
+        })
     }
 }
@@ -0,0 +1,105 @@
+use super::super::nodes::{Balance, Squad};
+use super::audit::Audit;
+use super::membership::join_squad;
+use crate::db::{
+    models,
+    schema::{balance, invite, node, squad},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{ErrorExtensions, FieldError};
+use chrono::Utc;
+use diesel::{prelude::*, result::Error::NotFound};
+use std::fmt;
+
+#[derive(async_graphql::InputObject)]
+pub struct AcceptInviteInput {
+    pub token: String,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct AcceptInvitePayload {
+    pub balance: Balance,
+    pub squad: Squad,
+}
+
+/// Refusal to accept an invite to a squad the person is already an active
+/// member of
+#[derive(Debug)]
+pub struct AlreadyMember;
+
+impl fmt::Display for AlreadyMember {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("You are already a member of this squad")
+    }
+}
+
+impl std::error::Error for AlreadyMember {}
+
+impl ErrorExtensions for AlreadyMember {
+    /// An error with the code `ALREADY_MEMBER`, which clients can recognize
+    fn extend(&self) -> FieldError {
+        FieldError::new(self.to_string())
+            .extend_with(|_, extensions| extensions.set("code", "ALREADY_MEMBER"))
+    }
+}
+
+/// Join the squad an invite is for as the signed-in person (identified by
+/// `person_id` and `email`), unless they are already a member of it
+pub async fn accept_invite(
+    pool: &Pool,
+    person_id: i32,
+    email: String,
+    input: AcceptInviteInput,
+    audit: Audit,
+) -> Result<AcceptInvitePayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let invite = invite::table
+                .filter(invite::token.eq(&input.token))
+                .for_update()
+                .get_result::<models::Invite>(conn)?;
+
+            // Invites addressed to an email may only be accepted by its owner
+            let email_matches = invite
+                .email
+                .as_ref()
+                .map_or(true, |invited| invited == &email.to_lowercase());
+
+            if !invite.is_usable(Utc::now()) || !email_matches {
+                return Err(NotFound);
+            }
+
+            let already_member = diesel::select(diesel::dsl::exists(
+                balance::table
+                    .filter(balance::person_id.eq(person_id))
+                    .filter(balance::squad_id.eq(invite.squad_id))
+                    .filter(balance::active),
+            ))
+            .get_result::<bool>(conn)?;
+            if already_member {
+                return Ok(Err(AlreadyMember));
+            }
+
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(squad::id.eq(invite.squad_id))
+                .filter(squad::archived.eq(false))
+                .get_result::<models::Squad>(conn)?;
+
+            let balance = join_squad(conn, person_id, squad.detail.id, models::MemberRole::Member)?;
+
+            diesel::update(&invite)
+                .set(invite::use_count.eq(invite::use_count + 1))
+                .execute(conn)?;
+
+            audit.record(conn, Some(squad.detail.id), vec![balance.node.uid])?;
+
+            Ok(Ok(AcceptInvitePayload {
+                balance: balance.into(),
+                squad: squad.into(),
+            }))
+        })
+        .await??)
+}
@@ -1,14 +1,18 @@
-use super::super::nodes::{Balance, Person, Squad};
+use super::super::{
+    nodes::{Balance, Person, Squad},
+    IdParser,
+};
+use super::audit::Audit;
+use super::membership::{join_squad, require_role};
 use crate::db::{
     models,
-    schema::{balance, node, person, squad},
+    schema::{node, person, squad},
+    traced::*,
     Pool,
 };
 use anyhow::Result;
-use async_graphql::{FieldError, FieldResult, ID};
+use async_graphql::{FieldResult, ID};
 use diesel::prelude::*;
-use std::convert::TryFrom;
-use tokio_diesel::*;
 use uuid::Uuid;
 
 #[derive(async_graphql::InputObject)]
@@ -22,14 +26,13 @@ pub struct ParsedAddPersonToSquadInput {
     squad_uid: Uuid,
 }
 
-impl TryFrom<AddPersonToSquadInput> for ParsedAddPersonToSquadInput {
-    type Error = FieldError;
-
-    fn try_from(value: AddPersonToSquadInput) -> FieldResult<ParsedAddPersonToSquadInput> {
-        let person_uid =
-            Uuid::parse_str(&value.person_id).or_else(|_e| Err(FieldError::from("Invalid ID")))?;
-        let squad_uid =
-            Uuid::parse_str(&value.squad_id).or_else(|_e| Err(FieldError::from("Invalid ID")))?;
+impl ParsedAddPersonToSquadInput {
+    pub fn parse(
+        value: AddPersonToSquadInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedAddPersonToSquadInput> {
+        let person_uid = ids.parse(&value.person_id, models::NodeType::Person)?;
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
 
         Ok(ParsedAddPersonToSquadInput {
             person_uid,
@@ -45,9 +48,41 @@ pub struct AddPersonToSquadPayload {
     pub squad: Squad,
 }
 
+/// Add a person to a squad on behalf of the signed-in person (identified by
+/// `viewer_id`), who must be a member of it who is allowed to contribute
 pub async fn add_person_to_squad(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedAddPersonToSquadInput,
+    audit: Audit,
+) -> Result<AddPersonToSquadPayload> {
+    add_to_squad(
+        pool,
+        Some(viewer_id),
+        input,
+        models::MemberRole::Member,
+        audit,
+    )
+    .await
+}
+
+/// Add a person to a squad with the given role, without checking whether
+/// anybody is permitted to do so. Only for use by administrators.
+pub async fn add_member(
     pool: &Pool,
     input: ParsedAddPersonToSquadInput,
+    role: models::MemberRole,
+    audit: Audit,
+) -> Result<AddPersonToSquadPayload> {
+    add_to_squad(pool, None, input, role, audit).await
+}
+
+async fn add_to_squad(
+    pool: &Pool,
+    viewer_id: Option<i32>,
+    input: ParsedAddPersonToSquadInput,
+    role: models::MemberRole,
+    audit: Audit,
 ) -> Result<AddPersonToSquadPayload> {
     Ok(pool
         .transaction(move |conn| {
@@ -59,29 +94,21 @@ pub async fn add_person_to_squad(
             let squad = node::table
                 .inner_join(squad::table)
                 .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
                 .get_result::<models::Squad>(conn)?;
 
-            let new_node = models::NewNode {
-                uid: Uuid::new_v4(),
-                node_type: models::NodeType::Balance,
-            };
-
-            let node = diesel::insert_into(node::table)
-                .values(new_node)
-                .get_result::<models::Node>(conn)?;
+            if let Some(viewer_id) = viewer_id {
+                require_role(
+                    conn,
+                    viewer_id,
+                    squad.detail.id,
+                    models::MemberRole::can_contribute,
+                )?;
+            }
 
-            let new_balance = models::NewBalance {
-                node_id: node.id,
-                person_id: person.detail.id,
-                squad_id: squad.detail.id,
-            };
+            let balance = join_squad(conn, person.detail.id, squad.detail.id, role)?;
 
-            let balance = diesel::insert_into(balance::table)
-                .values(&new_balance)
-                .get_result::<models::BalanceDetail>(conn)
-                .map(|detail| Balance {
-                    model: models::Balance { node, detail },
-                })?;
+            audit.record(conn, Some(squad.detail.id), vec![balance.node.uid])?;
 
             Ok(AddPersonToSquadPayload {
                 balance: balance.into(),
@@ -0,0 +1,98 @@
+use super::super::Viewer;
+use crate::db::{models, schema::audit_log, traced::TracedConnection};
+use crate::request_id::RequestId;
+use async_graphql::{Context, InputType};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+/// A mutation to be recorded in the audit log. Entries are written in the
+/// same database transaction as the changes they describe, so that every
+/// committed change has exactly one entry.
+pub struct Audit {
+    pub(super) operation: String,
+    pub(super) actor_id: Option<i32>,
+    pub(super) input: serde_json::Value,
+    pub(super) request_id: Option<String>,
+}
+
+impl Audit {
+    pub fn new(
+        operation: &str,
+        actor_id: Option<i32>,
+        input: serde_json::Value,
+        request_id: Option<String>,
+    ) -> Audit {
+        Audit {
+            operation: operation.to_string(),
+            actor_id,
+            input,
+            request_id,
+        }
+    }
+
+    /// Describe a mutation executed through the GraphQL API on behalf of the
+    /// signed-in viewer, if any
+    pub fn from_context<T: InputType>(context: &Context<'_>, operation: &str, input: &T) -> Audit {
+        Audit::new(
+            operation,
+            context
+                .data_opt::<Viewer>()
+                .and_then(|viewer| viewer.signed_in())
+                .map(|person| person.detail.id),
+            input
+                .to_value()
+                .into_json()
+                .unwrap_or(serde_json::Value::Null),
+            context
+                .data_opt::<RequestId>()
+                .map(|request_id| request_id.0.clone()),
+        )
+    }
+
+    /// Replace a secret field of the input snapshot, such as an invite token,
+    /// so that it is not readable from the audit log
+    pub fn redact(mut self, field: &str) -> Audit {
+        if let Some(value) = self.input.get_mut(field) {
+            *value = serde_json::Value::from("[redacted]");
+        }
+        self
+    }
+
+    /// Append the entry to the audit log, along with the squad affected by
+    /// the mutation and the IDs of the nodes it created or changed
+    pub(crate) fn record(
+        &self,
+        conn: &TracedConnection,
+        squad_id: Option<i32>,
+        node_uids: Vec<Uuid>,
+    ) -> QueryResult<()> {
+        let new_entry = models::NewAuditLogEntry {
+            squad_id,
+            actor_id: self.actor_id,
+            operation: &self.operation,
+            input: &self.input,
+            node_uids,
+            request_id: self.request_id.as_deref(),
+        };
+
+        diesel::insert_into(audit_log::table)
+            .values(&new_entry)
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Audit;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact() {
+        let audit = Audit::new("acceptInvite", None, json!({ "token": "secret" }), None)
+            .redact("token")
+            .redact("missing");
+
+        assert_eq!(json!({ "token": "[redacted]" }), audit.input);
+    }
+}
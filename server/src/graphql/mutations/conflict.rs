@@ -0,0 +1,41 @@
+use async_graphql::{ErrorExtensions, FieldError};
+use std::fmt;
+
+/// Refusal to change a node which has been changed by somebody else since the
+/// client read it, i.e. whose version is no longer the one the client expected
+#[derive(Debug)]
+pub struct Conflict {
+    pub current_version: i32,
+}
+
+impl Conflict {
+    /// Check that the version of a node, which must have been loaded `FOR
+    /// UPDATE` so that it cannot change until the transaction ends, is the one
+    /// the client expected
+    pub(super) fn check(expected_version: i32, current_version: i32) -> Result<(), Conflict> {
+        if expected_version == current_version {
+            Ok(())
+        } else {
+            Err(Conflict { current_version })
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("This has been changed by somebody else. Reload it and try again")
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+impl ErrorExtensions for Conflict {
+    /// An error with the code `CONFLICT`, which clients can recognize, and
+    /// the node's current version
+    fn extend(&self) -> FieldError {
+        FieldError::new(self.to_string()).extend_with(|_, extensions| {
+            extensions.set("code", "CONFLICT");
+            extensions.set("currentVersion", self.current_version);
+        })
+    }
+}
@@ -0,0 +1,165 @@
+use super::super::{IdParser, Invite};
+use super::audit::Audit;
+use super::membership::require_role;
+use crate::db::{
+    models,
+    schema::{invite, node, person, squad},
+    traced::*,
+    Pool,
+};
+use crate::mail::{Mailer, Message};
+use anyhow::Result;
+use async_graphql::{
+    validators::{Email, IntGreaterThan, IntRange},
+    FieldResult, ID,
+};
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use rand::{distributions::Alphanumeric, Rng};
+use uuid::Uuid;
+
+/// Length of the random token which identifies an invite
+const TOKEN_LEN: usize = 32;
+
+#[derive(async_graphql::InputObject)]
+pub struct CreateSquadInviteInput {
+    pub squad_id: ID,
+    /// Restrict the invite to the person with this email, who does not need
+    /// to have an account yet. The invite is emailed to them if the server
+    /// is set up to send email; otherwise its url must be shared with them
+    #[graphql(validator(Email))]
+    pub email: Option<String>,
+    /// How long the invite may be accepted for
+    #[graphql(default = 168, validator(IntRange(min = "1", max = "720")))]
+    pub expires_in_hours: i32,
+    /// How many people may accept the invite. Unlimited if not provided
+    #[graphql(validator(IntGreaterThan(value = "0")))]
+    pub max_uses: Option<i32>,
+}
+
+pub struct ParsedCreateSquadInviteInput {
+    squad_uid: Uuid,
+    email: Option<String>,
+    expires_at: DateTime<Utc>,
+    max_uses: Option<i32>,
+}
+
+impl ParsedCreateSquadInviteInput {
+    pub fn parse(
+        value: CreateSquadInviteInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedCreateSquadInviteInput> {
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
+
+        Ok(ParsedCreateSquadInviteInput {
+            squad_uid,
+            email: value.email.map(|email| email.to_lowercase()),
+            expires_at: Utc::now() + Duration::hours(value.expires_in_hours.into()),
+            max_uses: value.max_uses,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct CreateSquadInvitePayload {
+    pub invite: Invite,
+    /// Whether the invite was emailed to its `email`. If not, its url must be
+    /// shared with them some other way.
+    pub email_sent: bool,
+}
+
+/// Create an invite to a squad on behalf of the signed-in person (identified
+/// by `viewer_id`), who must be a member of it who is allowed to contribute.
+/// An invite restricted to an email is sent there with `mailer`, linking to
+/// the server named `server_name`. Failing to send it doesn't fail the
+/// mutation, as the invite can still be shared by hand.
+pub async fn create_squad_invite(
+    pool: &Pool,
+    mailer: &Mailer,
+    server_name: &str,
+    viewer_id: i32,
+    input: ParsedCreateSquadInviteInput,
+    audit: Audit,
+) -> Result<CreateSquadInvitePayload> {
+    let (invite, squad_name, inviter_name) = pool
+        .transaction(move |conn| {
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
+                .get_result::<models::Squad>(conn)?;
+
+            require_role(
+                conn,
+                viewer_id,
+                squad.detail.id,
+                models::MemberRole::can_contribute,
+            )?;
+
+            let token = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(TOKEN_LEN)
+                .collect::<String>();
+
+            let new_invite = models::NewInvite {
+                squad_id: squad.detail.id,
+                created_by: viewer_id,
+                token: &token,
+                email: input.email.as_deref(),
+                expires_at: input.expires_at,
+                max_uses: input.max_uses,
+            };
+
+            let invite = diesel::insert_into(invite::table)
+                .values(&new_invite)
+                .get_result::<models::Invite>(conn)?;
+
+            audit.record(conn, Some(squad.detail.id), vec![squad.node.uid])?;
+
+            let inviter_name = person::table
+                .find(viewer_id)
+                .select(person::display_name)
+                .get_result::<String>(conn)?;
+
+            Ok((invite, squad.detail.display_name, inviter_name))
+        })
+        .await?;
+
+    let email_sent = match &invite.email {
+        Some(email) => {
+            let message = invite_message(&invite, email, &squad_name, &inviter_name, server_name);
+            mailer.send(&message).await.unwrap_or_else(|e| {
+                log::error!("Failed to email invite {}: {:#}", invite.id, e);
+                false
+            })
+        }
+        None => false,
+    };
+
+    Ok(CreateSquadInvitePayload {
+        invite: invite.into(),
+        email_sent,
+    })
+}
+
+/// The email telling the person at `email` about the invite
+fn invite_message(
+    invite: &models::Invite,
+    email: &str,
+    squad_name: &str,
+    inviter_name: &str,
+    server_name: &str,
+) -> Message {
+    Message {
+        to: email.to_string(),
+        subject: format!("{} invited you to join {}", inviter_name, squad_name),
+        text: format!(
+            "{} invited you to join {} on {}. Accept the invite before {} at\n\n{}\n",
+            inviter_name,
+            squad_name,
+            server_name,
+            invite.expires_at.format("%B %-d, %Y %H:%M UTC"),
+            Invite::url_for(server_name, &invite.token),
+        ),
+    }
+}
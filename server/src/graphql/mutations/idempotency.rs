@@ -0,0 +1,246 @@
+use super::audit::Audit;
+use crate::db::{
+    models,
+    schema::{idempotency_key, node, squad, txn},
+    traced::TracedConnection,
+};
+use async_graphql::{ErrorExtensions, FieldError};
+use diesel::{prelude::*, result::Error::RollbackTransaction};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// A key chosen by the client (its `clientMutationId`) to identify a
+/// mutation, so that retrying it, e.g. after a dropped connection, returns the
+/// original result instead of applying the mutation again. Keys are scoped to
+/// the person who used them.
+pub struct IdempotencyKey {
+    person_id: i32,
+    key: String,
+    operation: String,
+    input_hash: String,
+}
+
+/// Refusal to apply a mutation whose key was already used for a different
+/// operation, or the same one with different input
+#[derive(Debug)]
+pub struct KeyReused;
+
+impl fmt::Display for KeyReused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("This clientMutationId has already been used for a different mutation")
+    }
+}
+
+impl std::error::Error for KeyReused {}
+
+impl ErrorExtensions for KeyReused {
+    /// An error with the code `CLIENT_MUTATION_ID_REUSED`, which clients can
+    /// recognize
+    fn extend(&self) -> FieldError {
+        FieldError::new(self.to_string())
+            .extend_with(|_, extensions| extensions.set("code", "CLIENT_MUTATION_ID_REUSED"))
+    }
+}
+
+impl IdempotencyKey {
+    /// The key for a mutation by the signed-in person (identified by
+    /// `viewer_id`), described by `audit`, if the client provided one
+    pub fn new(viewer_id: i32, audit: &Audit, key: Option<String>) -> Option<IdempotencyKey> {
+        key.map(|key| IdempotencyKey {
+            person_id: viewer_id,
+            key,
+            operation: audit.operation.clone(),
+            input_hash: format!("{:x}", Sha256::digest(audit.input.to_string().as_bytes())),
+        })
+    }
+
+    /// Claim the key for this mutation, waiting for any concurrent mutation
+    /// using the same key to commit or roll back first. If the key has
+    /// already been used, returns the ID of the node the earlier mutation
+    /// created or changed, and the mutation must not be applied again. A key
+    /// may not be reused for a different operation or different input. Fails
+    /// if the key was committed without a node, which should never happen.
+    pub(super) fn claim(
+        &self,
+        conn: &TracedConnection,
+    ) -> QueryResult<Result<Option<i32>, KeyReused>> {
+        let new_key = models::NewIdempotencyKey {
+            person_id: self.person_id,
+            key: &self.key,
+            operation: &self.operation,
+            input_hash: &self.input_hash,
+        };
+
+        let claimed = diesel::insert_into(idempotency_key::table)
+            .values(&new_key)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if claimed == 1 {
+            return Ok(Ok(None));
+        }
+
+        let (operation, input_hash, node_id) = idempotency_key::table
+            .find((self.person_id, &self.key))
+            .select((
+                idempotency_key::operation,
+                idempotency_key::input_hash,
+                idempotency_key::node_id,
+            ))
+            .get_result::<(String, Option<String>, Option<i32>)>(conn)?;
+
+        // Keys claimed before input hashes were recorded match any input
+        let same_input = match input_hash {
+            Some(input_hash) => input_hash == self.input_hash,
+            None => true,
+        };
+        if operation != self.operation || !same_input {
+            return Ok(Err(KeyReused));
+        }
+
+        match node_id {
+            Some(node_id) => Ok(Ok(Some(node_id))),
+            None => {
+                // A key is only committed along with the node its mutation
+                // created or changed, or released if it changed nothing
+                log::error!(
+                    "Idempotency key {:?} of person {} was committed without a node",
+                    self.key,
+                    self.person_id
+                );
+                Err(RollbackTransaction)
+            }
+        }
+    }
+
+    /// Give up the key after the mutation failed without changing anything,
+    /// e.g. because of a conflict, so that it may be retried with the same
+    /// key. Must be called in the same transaction as `claim`.
+    pub(super) fn release(&self, conn: &TracedConnection) -> QueryResult<()> {
+        diesel::delete(idempotency_key::table.find((self.person_id, &self.key)))
+            .filter(idempotency_key::node_id.is_null())
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Record the node the mutation created or changed, to be returned when
+    /// it is retried. Must be called in the same transaction as `claim`.
+    pub(super) fn complete(&self, conn: &TracedConnection, node_id: i32) -> QueryResult<()> {
+        diesel::update(idempotency_key::table.find((self.person_id, &self.key)))
+            .set(idempotency_key::node_id.eq(node_id))
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+/// Load the transaction identified by `node_id`, and its squad, to replay a
+/// mutation which recorded or changed it
+pub(super) fn replay_transaction(
+    conn: &TracedConnection,
+    node_id: i32,
+) -> QueryResult<(models::Squad, models::Transaction)> {
+    let transaction = node::table
+        .inner_join(txn::table)
+        .filter(node::id.eq(node_id))
+        .get_result::<models::Transaction>(conn)?;
+
+    let squad = node::table
+        .inner_join(squad::table)
+        .filter(squad::id.eq(transaction.detail.squad_id))
+        .get_result::<models::Squad>(conn)?;
+
+    Ok((squad, transaction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        new_transaction,
+        tests::{audit, person, squad_of_two, SquadOfTwo},
+        NewTransactionPayload, ParsedNewTransactionInput,
+    };
+    use super::*;
+    use crate::db::{tests::test_pool, traced::*, Pool};
+    use serde_json::json;
+
+    /// Record a transaction moving `amount_cents` between the squad's two
+    /// balances, identified by the client as `key`
+    async fn record(
+        pool: &Pool,
+        squad: &SquadOfTwo,
+        key: &str,
+        amount_cents: i32,
+    ) -> anyhow::Result<NewTransactionPayload> {
+        let [payer, payee] = squad.balance_uids;
+        let input = ParsedNewTransactionInput {
+            squad_uid: squad.squad_uid,
+            balance_changes_detail: vec![(payer, amount_cents), (payee, -amount_cents)]
+                .into_iter()
+                .collect(),
+            kind: models::TransactionKind::Expense,
+            description: String::new(),
+            occurred_at: None,
+            client_mutation_id: Some(key.to_string()),
+        };
+        let audit = audit(
+            "newTransaction",
+            squad.owner_id,
+            json!({ "amountCents": amount_cents, "clientMutationId": key }),
+        );
+
+        new_transaction(pool, squad.owner_id, input, audit).await
+    }
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn test_same_input_replays() {
+        let pool = test_pool();
+        let squad = squad_of_two(&pool).await;
+
+        let first = record(&pool, &squad, "record", 100).await.unwrap();
+        let retried = record(&pool, &squad, "record", 100).await.unwrap();
+        assert_eq!(
+            retried.transaction.model.node.id,
+            first.transaction.model.node.id
+        );
+
+        let other = record(&pool, &squad, "another", 100).await.unwrap();
+        assert_ne!(
+            other.transaction.model.node.id,
+            first.transaction.model.node.id
+        );
+    }
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn test_different_input_is_refused() {
+        let pool = test_pool();
+        let squad = squad_of_two(&pool).await;
+
+        record(&pool, &squad, "record", 100).await.unwrap();
+        let error = record(&pool, &squad, "record", 200).await.err().unwrap();
+        let key_reused = error.downcast_ref::<KeyReused>().unwrap();
+        assert_eq!(
+            serde_json::to_value(key_reused.extend()).unwrap()["extensions"],
+            json!({ "code": "CLIENT_MUTATION_ID_REUSED" })
+        );
+    }
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn test_key_without_node_fails() {
+        let pool = test_pool();
+        let person_id = person(&pool).await;
+        let audit = audit("newTransaction", person_id, json!({}));
+        let key = IdempotencyKey::new(person_id, &audit, Some(String::from("record"))).unwrap();
+
+        // As if a mutation had claimed the key, then committed without
+        // completing or releasing it
+        let claimed = pool
+            .transaction(move |conn| {
+                assert_eq!(key.claim(conn)?.unwrap(), None);
+                key.claim(conn)
+            })
+            .await;
+        assert!(claimed.is_err());
+    }
+}
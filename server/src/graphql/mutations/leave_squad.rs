@@ -0,0 +1,83 @@
+use super::super::{
+    nodes::{Balance, Squad, Transaction},
+    IdParser,
+};
+use super::audit::Audit;
+use super::remove_person_from_squad::{affected_uids, deactivate_balance};
+use crate::db::{
+    models,
+    schema::{node, squad},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{FieldResult, ID};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct LeaveSquadInput {
+    pub squad_id: ID,
+    /// Settle an outstanding balance between the remaining members instead
+    /// of refusing to leave
+    #[graphql(default)]
+    pub settle_up: bool,
+}
+
+pub struct ParsedLeaveSquadInput {
+    squad_uid: Uuid,
+    settle_up: bool,
+}
+
+impl ParsedLeaveSquadInput {
+    pub fn parse(value: LeaveSquadInput, ids: &IdParser) -> FieldResult<ParsedLeaveSquadInput> {
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
+
+        Ok(ParsedLeaveSquadInput {
+            squad_uid,
+            settle_up: value.settle_up,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct LeaveSquadPayload {
+    pub balance: Balance,
+    pub squad: Squad,
+    /// The transaction which settled the viewer's balance, if one was needed
+    pub settlement: Option<Transaction>,
+}
+
+/// Remove the signed-in person (identified by `person_id`) from a squad. The
+/// squad's last owner may not leave it.
+pub async fn leave_squad(
+    pool: &Pool,
+    person_id: i32,
+    input: ParsedLeaveSquadInput,
+    audit: Audit,
+) -> Result<LeaveSquadPayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
+                .get_result::<models::Squad>(conn)?;
+
+            let (balance, settlement) =
+                deactivate_balance(conn, person_id, person_id, &squad, input.settle_up)?;
+
+            audit.record(
+                conn,
+                Some(squad.detail.id),
+                affected_uids(&balance, &settlement),
+            )?;
+
+            Ok(LeaveSquadPayload {
+                balance: balance.into(),
+                squad: squad.into(),
+                settlement: settlement.map(|t| t.into()),
+            })
+        })
+        .await?)
+}
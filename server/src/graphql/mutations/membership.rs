@@ -0,0 +1,103 @@
+use crate::db::{
+    models,
+    schema::{balance, node},
+    traced::TracedConnection,
+};
+use diesel::{prelude::*, result::Error::NotFound, OptionalExtension};
+use uuid::Uuid;
+
+/// Find a person's active balance in a squad, failing if they are not a
+/// member of it
+pub(super) fn require_membership(
+    conn: &TracedConnection,
+    person_id: i32,
+    squad_id: i32,
+) -> QueryResult<models::BalanceDetail> {
+    balance::table
+        .filter(balance::person_id.eq(person_id))
+        .filter(balance::squad_id.eq(squad_id))
+        .filter(balance::active.eq(true))
+        .get_result::<models::BalanceDetail>(conn)
+}
+
+/// Find a person's active balance in a squad, failing unless they are a
+/// member whose role satisfies `permitted`
+pub(super) fn require_role(
+    conn: &TracedConnection,
+    person_id: i32,
+    squad_id: i32,
+    permitted: fn(models::MemberRole) -> bool,
+) -> QueryResult<models::BalanceDetail> {
+    let membership = require_membership(conn, person_id, squad_id)?;
+
+    if permitted(membership.role) {
+        Ok(membership)
+    } else {
+        Err(NotFound)
+    }
+}
+
+/// Whether a squad has an active owner other than the given balance, so that
+/// the balance may stop being an owner without orphaning the squad
+pub(super) fn has_other_owner(
+    conn: &TracedConnection,
+    squad_id: i32,
+    balance_id: i32,
+) -> QueryResult<bool> {
+    use diesel::dsl::{exists, select};
+
+    select(exists(
+        balance::table
+            .filter(balance::squad_id.eq(squad_id))
+            .filter(balance::active.eq(true))
+            .filter(balance::role.eq(models::MemberRole::Owner))
+            .filter(balance::id.ne(balance_id)),
+    ))
+    .get_result::<bool>(conn)
+}
+
+/// Make a person an active member of a squad. A person who previously left
+/// the squad gets their old balance back, so that its history is preserved.
+pub(super) fn join_squad(
+    conn: &TracedConnection,
+    person_id: i32,
+    squad_id: i32,
+    role: models::MemberRole,
+) -> QueryResult<models::Balance> {
+    let inactive = node::table
+        .inner_join(balance::table)
+        .filter(balance::person_id.eq(person_id))
+        .filter(balance::squad_id.eq(squad_id))
+        .filter(balance::active.eq(false))
+        .get_result::<models::Balance>(conn)
+        .optional()?;
+
+    if let Some(inactive) = inactive {
+        let node = inactive.node;
+        return diesel::update(&inactive.detail)
+            .set((balance::active.eq(true), balance::role.eq(role)))
+            .get_result::<models::BalanceDetail>(conn)
+            .map(|detail| models::Balance { node, detail });
+    }
+
+    let new_node = models::NewNode {
+        uid: Uuid::new_v4(),
+        node_type: models::NodeType::Balance,
+    };
+
+    let node = diesel::insert_into(node::table)
+        .values(new_node)
+        .get_result::<models::Node>(conn)?;
+
+    let new_balance = models::NewBalance {
+        node_id: node.id,
+        person_id,
+        squad_id,
+        role,
+    };
+
+    diesel::insert_into(balance::table)
+        .values(&new_balance)
+        .get_result::<models::BalanceDetail>(conn)
+        .map(|detail| models::Balance { node, detail })
+}
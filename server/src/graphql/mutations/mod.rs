@@ -1,9 +1,105 @@
+mod accept_invite;
 mod add_person_to_squad;
+mod archive_squad;
+mod audit;
+mod conflict;
+mod create_squad_invite;
+mod idempotency;
+mod leave_squad;
+mod membership;
 mod new_person;
 mod new_squad;
 mod new_transaction;
+mod remove_person_from_squad;
+mod set_member_role;
+mod update_person;
+mod update_squad;
+mod void_transaction;
 
+pub use accept_invite::*;
 pub use add_person_to_squad::*;
+pub use archive_squad::*;
+pub use audit::*;
+pub use conflict::Conflict;
+pub use create_squad_invite::*;
+pub use idempotency::KeyReused;
+pub use leave_squad::*;
 pub use new_person::*;
 pub use new_squad::*;
 pub use new_transaction::*;
+pub use remove_person_from_squad::*;
+pub use set_member_role::*;
+pub use update_person::*;
+pub use update_squad::*;
+pub use void_transaction::*;
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::membership::join_squad;
+    use super::*;
+    use crate::db::{models, traced::*, Pool};
+    use uuid::Uuid;
+
+    /// A squad owned by a new person, with one other new member
+    pub struct SquadOfTwo {
+        pub owner_id: i32,
+        pub squad_uid: Uuid,
+        /// The owner's balance, then the other member's
+        pub balance_uids: [Uuid; 2],
+    }
+
+    /// Describe a mutation made by a test on behalf of `actor_id`
+    pub fn audit(operation: &str, actor_id: i32, input: serde_json::Value) -> Audit {
+        Audit::new(operation, Some(actor_id), input, None)
+    }
+
+    /// Sign up somebody new, returning their ID
+    pub async fn person(pool: &Pool) -> i32 {
+        let name = Uuid::new_v4().to_string();
+        let input = NewPersonInput {
+            email: format!("{}@example.com", name),
+            display_name: name,
+            first_name: String::new(),
+            last_name: String::new(),
+        };
+        let audit = Audit::new("newPerson", None, serde_json::Value::Null, None);
+
+        new_person(pool, input, audit)
+            .await
+            .unwrap()
+            .person
+            .model
+            .detail
+            .id
+    }
+
+    pub async fn squad_of_two(pool: &Pool) -> SquadOfTwo {
+        let owner_id = person(pool).await;
+        let member_id = person(pool).await;
+        let input = NewSquadInput {
+            display_name: String::from("Test"),
+        };
+        let payload = new_squad(
+            pool,
+            owner_id,
+            input,
+            audit("newSquad", owner_id, serde_json::Value::Null),
+        )
+        .await
+        .unwrap();
+
+        let squad_id = payload.squad.model.detail.id;
+        let member = pool
+            .transaction(move |conn| {
+                join_squad(conn, member_id, squad_id, models::MemberRole::Member)
+            })
+            .await
+            .unwrap();
+
+        SquadOfTwo {
+            owner_id,
+            squad_uid: payload.squad.model.node.uid,
+            balance_uids: [payload.balance.model.node.uid, member.node.uid],
+        }
+    }
+}
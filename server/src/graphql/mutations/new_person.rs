@@ -1,12 +1,13 @@
 use super::super::nodes::Person;
+use super::audit::Audit;
 use crate::db::{
     models,
     schema::{node, person},
+    traced::*,
     Pool,
 };
 use async_graphql::validators::Email;
 use diesel::prelude::*;
-use tokio_diesel::*;
 use uuid::Uuid;
 
 #[derive(async_graphql::InputObject)]
@@ -23,7 +24,11 @@ pub struct NewPersonPayload {
     pub person: Person,
 }
 
-pub async fn new_person(pool: &Pool, input: NewPersonInput) -> AsyncResult<NewPersonPayload> {
+pub async fn new_person(
+    pool: &Pool,
+    input: NewPersonInput,
+    audit: Audit,
+) -> AsyncResult<NewPersonPayload> {
     pool.transaction(move |conn| {
         let new_node = models::NewNode {
             uid: Uuid::new_v4(),
@@ -42,12 +47,20 @@ pub async fn new_person(pool: &Pool, input: NewPersonInput) -> AsyncResult<NewPe
             last_name: &input.last_name,
         };
 
-        diesel::insert_into(person::table)
+        let detail = diesel::insert_into(person::table)
             .values(&new_person)
-            .get_result::<models::PersonDetail>(conn)
-            .map(|detail| NewPersonPayload {
-                person: models::Person { node, detail }.into(),
-            })
+            .get_result::<models::PersonDetail>(conn)?;
+
+        // People signing up act on their own behalf
+        Audit {
+            actor_id: Some(detail.id),
+            ..audit
+        }
+        .record(conn, None, vec![node.uid])?;
+
+        Ok(NewPersonPayload {
+            person: models::Person { node, detail }.into(),
+        })
     })
     .await
 }
@@ -1,11 +1,13 @@
-use super::super::nodes::Squad;
+use super::super::nodes::{Balance, Squad};
+use super::audit::Audit;
+use super::membership::join_squad;
 use crate::db::{
     models,
     schema::{node, squad},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
 use uuid::Uuid;
 
 #[derive(async_graphql::InputObject)]
@@ -16,9 +18,17 @@ pub struct NewSquadInput {
 #[derive(async_graphql::SimpleObject)]
 pub struct NewSquadPayload {
     pub squad: Squad,
+    /// The balance of the person who created, and therefore owns, the squad
+    pub balance: Balance,
 }
 
-pub async fn new_squad(pool: &Pool, input: NewSquadInput) -> AsyncResult<NewSquadPayload> {
+/// Create a squad owned by the signed-in person (identified by `viewer_id`)
+pub async fn new_squad(
+    pool: &Pool,
+    viewer_id: i32,
+    input: NewSquadInput,
+    audit: Audit,
+) -> AsyncResult<NewSquadPayload> {
     pool.transaction(move |conn| {
         let new_node = models::NewNode {
             uid: Uuid::new_v4(),
@@ -34,12 +44,18 @@ pub async fn new_squad(pool: &Pool, input: NewSquadInput) -> AsyncResult<NewSqua
             display_name: &input.display_name,
         };
 
-        diesel::insert_into(squad::table)
+        let detail = diesel::insert_into(squad::table)
             .values(&new_squad)
-            .get_result::<models::SquadDetail>(conn)
-            .map(|detail| NewSquadPayload {
-                squad: models::Squad { node, detail }.into(),
-            })
+            .get_result::<models::SquadDetail>(conn)?;
+
+        let balance = join_squad(conn, viewer_id, detail.id, models::MemberRole::Owner)?;
+
+        audit.record(conn, Some(detail.id), vec![node.uid, balance.node.uid])?;
+
+        Ok(NewSquadPayload {
+            squad: models::Squad { node, detail }.into(),
+            balance: balance.into(),
+        })
     })
     .await
 }
@@ -1,18 +1,25 @@
-use super::super::nodes::{Squad, Transaction};
+use super::super::{
+    nodes::{Squad, Transaction},
+    IdParser,
+};
+use super::audit::Audit;
+use super::idempotency::{replay_transaction, IdempotencyKey};
+use super::membership::require_role;
 use crate::db::{
     models,
     schema::{balance, node, squad, txn, txn_part},
+    traced::*,
     Pool,
 };
 use anyhow::Result;
 use async_graphql::{
-    validators::{InputValueValidator, IntNonZero, ListMinLength},
-    FieldError, FieldResult, Value, ID,
+    validators::{InputValueValidator, IntNonZero, ListMinLength, StringMaxLength},
+    FieldResult, Value, ID,
 };
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use tokio_diesel::*;
 use uuid::Uuid;
 
 struct ChangesSumToZero {}
@@ -52,12 +59,12 @@ pub struct ParsedBalanceChangeDetail {
     pub change_cents: i32,
 }
 
-impl TryFrom<BalanceChangeDetail> for ParsedBalanceChangeDetail {
-    type Error = FieldError;
-
-    fn try_from(value: BalanceChangeDetail) -> FieldResult<ParsedBalanceChangeDetail> {
-        let balance_uid =
-            Uuid::parse_str(&value.balance_id).or_else(|_e| Err(FieldError::from("Invalid ID")))?;
+impl ParsedBalanceChangeDetail {
+    pub fn parse(
+        value: BalanceChangeDetail,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedBalanceChangeDetail> {
+        let balance_uid = ids.parse(&value.balance_id, models::NodeType::Balance)?;
 
         Ok(ParsedBalanceChangeDetail {
             balance_uid,
@@ -71,19 +78,33 @@ pub struct NewTransactionInput {
     pub squad_id: ID,
     #[graphql(validator(and(ListMinLength(length = "1"), ChangesSumToZero)))]
     pub balance_changes_detail: Vec<BalanceChangeDetail>,
+    #[graphql(default_with = "models::TransactionKind::Expense")]
+    pub kind: models::TransactionKind,
+    #[graphql(default, validator(StringMaxLength(length = "255")))]
+    pub description: String,
+    /// When the transaction happened, if not now
+    pub occurred_at: Option<DateTime<Utc>>,
+    /// Identifies this mutation, so that if it is retried with the same ID the
+    /// transaction already recorded is returned instead of a duplicate
+    #[graphql(validator(StringMaxLength(length = "255")))]
+    pub client_mutation_id: Option<String>,
 }
 
 pub struct ParsedNewTransactionInput {
     pub squad_uid: Uuid,
     pub balance_changes_detail: HashMap<Uuid, i32>,
+    pub kind: models::TransactionKind,
+    pub description: String,
+    pub occurred_at: Option<DateTime<Utc>>,
+    pub client_mutation_id: Option<String>,
 }
 
-impl TryFrom<NewTransactionInput> for ParsedNewTransactionInput {
-    type Error = FieldError;
-
-    fn try_from(value: NewTransactionInput) -> FieldResult<ParsedNewTransactionInput> {
-        let squad_uid =
-            Uuid::parse_str(&value.squad_id).or_else(|_e| Err(FieldError::from("Invalid ID")))?;
+impl ParsedNewTransactionInput {
+    pub fn parse(
+        value: NewTransactionInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedNewTransactionInput> {
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
 
         Ok(ParsedNewTransactionInput {
             squad_uid,
@@ -91,32 +112,69 @@ impl TryFrom<NewTransactionInput> for ParsedNewTransactionInput {
                 .balance_changes_detail
                 .into_iter()
                 .map(|b| {
-                    let parsed = ParsedBalanceChangeDetail::try_from(b)?;
+                    let parsed = ParsedBalanceChangeDetail::parse(b, ids)?;
 
                     Ok((parsed.balance_uid, parsed.change_cents))
                 })
                 .collect::<FieldResult<HashMap<Uuid, i32>>>()?,
+            kind: value.kind,
+            description: value.description,
+            occurred_at: value.occurred_at,
+            client_mutation_id: value.client_mutation_id,
         })
     }
 }
 
 #[derive(async_graphql::SimpleObject)]
 pub struct NewTransactionPayload {
+    pub client_mutation_id: Option<String>,
     pub squad: Squad,
     pub transaction: Transaction,
 }
 
+/// Record a transaction on behalf of the signed-in person (identified by
+/// `viewer_id`), who must be a member of the squad who is allowed to
+/// contribute. If the mutation is a retry of one already applied, the
+/// transaction recorded then is returned instead.
 pub async fn new_transaction(
     pool: &Pool,
+    viewer_id: i32,
     input: ParsedNewTransactionInput,
+    audit: Audit,
 ) -> Result<NewTransactionPayload> {
+    let key = IdempotencyKey::new(viewer_id, &audit, input.client_mutation_id.clone());
+
     Ok(pool
         .transaction(move |conn| {
+            let replayed = match &key {
+                Some(key) => match key.claim(conn)? {
+                    Ok(replayed) => replayed,
+                    Err(key_reused) => return Ok(Err(key_reused)),
+                },
+                None => None,
+            };
+            if let Some(node_id) = replayed {
+                let (squad, transaction) = replay_transaction(conn, node_id)?;
+                return Ok(Ok(NewTransactionPayload {
+                    client_mutation_id: input.client_mutation_id,
+                    squad: squad.into(),
+                    transaction: transaction.into(),
+                }));
+            }
+
             let squad = node::table
                 .inner_join(squad::table)
                 .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
                 .get_result::<models::Squad>(conn)?;
 
+            require_role(
+                conn,
+                viewer_id,
+                squad.detail.id,
+                models::MemberRole::can_contribute,
+            )?;
+
             let new_node = models::NewNode {
                 uid: Uuid::new_v4(),
                 node_type: models::NodeType::Txn,
@@ -126,9 +184,21 @@ pub async fn new_transaction(
                 .values(new_node)
                 .get_result::<models::Node>(conn)?;
 
+            let amount_cents = input
+                .balance_changes_detail
+                .values()
+                .filter(|&&change| change > 0)
+                .try_fold(0i32, |sum, &change| sum.checked_add(change))
+                .ok_or(diesel::result::Error::RollbackTransaction)?;
+
             let new_transaction = models::NewTransaction {
                 node_id: node.id,
                 squad_id: squad.detail.id,
+                created_by: Some(viewer_id),
+                kind: input.kind,
+                description: &input.description,
+                amount_cents,
+                occurred_at: input.occurred_at.unwrap_or_else(Utc::now),
             };
 
             let transaction = diesel::insert_into(txn::table)
@@ -143,8 +213,15 @@ pub async fn new_transaction(
             let balances = node::table
                 .inner_join(balance::table)
                 .filter(node::uid.eq_any(balance_uids))
+                .filter(balance::squad_id.eq(squad.detail.id))
+                .filter(balance::active.eq(true))
                 .get_results::<models::Balance>(conn)?;
 
+            // Every change must apply to an active balance in this squad
+            if balances.len() != input.balance_changes_detail.len() {
+                return Err(diesel::result::Error::NotFound);
+            }
+
             let new_parts = balances
                 .iter()
                 .map(|balance| {
@@ -163,10 +240,21 @@ pub async fn new_transaction(
                 .values(new_parts)
                 .execute(conn)?;
 
-            Ok(NewTransactionPayload {
+            if let Some(key) = &key {
+                key.complete(conn, transaction.model.node.id)?;
+            }
+
+            audit.record(
+                conn,
+                Some(squad.detail.id),
+                vec![transaction.model.node.uid],
+            )?;
+
+            Ok(Ok(NewTransactionPayload {
+                client_mutation_id: input.client_mutation_id,
                 squad: squad.into(),
                 transaction: transaction.into(),
-            })
+            }))
         })
-        .await?)
+        .await??)
 }
@@ -0,0 +1,282 @@
+use super::super::{
+    nodes::{Balance, Person, Squad, Transaction},
+    IdParser,
+};
+use super::audit::Audit;
+use super::membership::{has_other_owner, require_membership, require_role};
+use crate::db::{
+    models,
+    schema::{balance, node, person, squad, txn, txn_part},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{FieldResult, ID};
+use chrono::Utc;
+use diesel::{
+    prelude::*,
+    result::Error::{NotFound, RollbackTransaction},
+};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct RemovePersonFromSquadInput {
+    pub person_id: ID,
+    pub squad_id: ID,
+    /// Settle an outstanding balance between the remaining members instead
+    /// of refusing to remove the person
+    #[graphql(default)]
+    pub settle_up: bool,
+}
+
+pub struct ParsedRemovePersonFromSquadInput {
+    person_uid: Uuid,
+    squad_uid: Uuid,
+    settle_up: bool,
+}
+
+impl ParsedRemovePersonFromSquadInput {
+    pub fn parse(
+        value: RemovePersonFromSquadInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedRemovePersonFromSquadInput> {
+        let person_uid = ids.parse(&value.person_id, models::NodeType::Person)?;
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
+
+        Ok(ParsedRemovePersonFromSquadInput {
+            person_uid,
+            squad_uid,
+            settle_up: value.settle_up,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct RemovePersonFromSquadPayload {
+    pub balance: Balance,
+    pub person: Person,
+    pub squad: Squad,
+    /// The transaction which settled the person's balance, if one was needed
+    pub settlement: Option<Transaction>,
+}
+
+/// Remove a person from a squad on behalf of the signed-in person (identified
+/// by `viewer_id`), who must be one of its owners or admins. Only owners may
+/// remove other owners.
+pub async fn remove_person_from_squad(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedRemovePersonFromSquadInput,
+    audit: Audit,
+) -> Result<RemovePersonFromSquadPayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let person = node::table
+                .inner_join(person::table)
+                .filter(node::uid.eq(input.person_uid))
+                .get_result::<models::Person>(conn)?;
+
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
+                .get_result::<models::Squad>(conn)?;
+
+            let viewer_membership = require_role(
+                conn,
+                viewer_id,
+                squad.detail.id,
+                models::MemberRole::can_manage,
+            )?;
+
+            let membership = require_membership(conn, person.detail.id, squad.detail.id)?;
+
+            if membership.role == models::MemberRole::Owner
+                && viewer_membership.role != models::MemberRole::Owner
+            {
+                return Err(NotFound);
+            }
+
+            let (balance, settlement) =
+                deactivate_balance(conn, viewer_id, person.detail.id, &squad, input.settle_up)?;
+
+            audit.record(
+                conn,
+                Some(squad.detail.id),
+                affected_uids(&balance, &settlement),
+            )?;
+
+            Ok(RemovePersonFromSquadPayload {
+                balance: balance.into(),
+                person: person.into(),
+                squad: squad.into(),
+                settlement: settlement.map(|t| t.into()),
+            })
+        })
+        .await?)
+}
+
+/// IDs of the balance deactivated by `deactivate_balance` and the transaction
+/// which settled it, if any
+pub(super) fn affected_uids(
+    balance: &models::Balance,
+    settlement: &Option<models::Transaction>,
+) -> Vec<Uuid> {
+    let mut uids = vec![balance.node.uid];
+    uids.extend(settlement.iter().map(|transaction| transaction.node.uid));
+    uids
+}
+
+/// Mark a person's balance in a squad as inactive. The balance row and its
+/// transaction parts are kept so that the squad's history stays intact. The
+/// squad's last owner may not be removed.
+///
+/// Fails if the balance total is nonzero, unless `settle_up` is set, in which
+/// case a transaction is first recorded on behalf of `actor_id` that zeroes
+/// the balance by splitting its total evenly across the squad's other active
+/// members.
+pub(super) fn deactivate_balance(
+    conn: &TracedConnection,
+    actor_id: i32,
+    person_id: i32,
+    squad: &models::Squad,
+    settle_up: bool,
+) -> QueryResult<(models::Balance, Option<models::Transaction>)> {
+    use diesel::dsl::sum;
+
+    let balance = node::table
+        .inner_join(balance::table)
+        .filter(balance::person_id.eq(person_id))
+        .filter(balance::squad_id.eq(squad.detail.id))
+        .filter(balance::active.eq(true))
+        .get_result::<models::Balance>(conn)?;
+
+    if balance.detail.role == models::MemberRole::Owner
+        && !has_other_owner(conn, squad.detail.id, balance.detail.id)?
+    {
+        return Err(RollbackTransaction);
+    }
+
+    let total = txn_part::table
+        .inner_join(txn::table)
+        .filter(txn_part::balance_id.eq(balance.detail.id))
+        .filter(txn::voided.eq(false))
+        .select(sum(txn_part::balance_change_cents))
+        .get_result::<Option<i64>>(conn)?
+        .map(|n| i32::try_from(n).expect("Exceeded maximum representable balance"))
+        .unwrap_or(0);
+
+    let settlement = match (total, settle_up) {
+        (0, _) => None,
+        (_, true) => Some(settle_balance(conn, actor_id, squad, &balance, total)?),
+        (_, false) => return Err(RollbackTransaction),
+    };
+
+    let node = balance.node;
+    let detail = diesel::update(&balance.detail)
+        .set(balance::active.eq(false))
+        .get_result::<models::BalanceDetail>(conn)?;
+
+    Ok((models::Balance { node, detail }, settlement))
+}
+
+/// Record a transaction moving `total` out of `balance` and into the squad's
+/// other active balances
+fn settle_balance(
+    conn: &TracedConnection,
+    actor_id: i32,
+    squad: &models::Squad,
+    balance: &models::Balance,
+    total: i32,
+) -> QueryResult<models::Transaction> {
+    let others = balance::table
+        .filter(balance::squad_id.eq(squad.detail.id))
+        .filter(balance::active.eq(true))
+        .filter(balance::id.ne(balance.detail.id))
+        .order(balance::id)
+        .select(balance::id)
+        .get_results::<i32>(conn)?;
+
+    if others.is_empty() {
+        return Err(RollbackTransaction);
+    }
+
+    let new_node = models::NewNode {
+        uid: Uuid::new_v4(),
+        node_type: models::NodeType::Txn,
+    };
+
+    let node = diesel::insert_into(node::table)
+        .values(new_node)
+        .get_result::<models::Node>(conn)?;
+
+    let new_transaction = models::NewTransaction {
+        node_id: node.id,
+        squad_id: squad.detail.id,
+        created_by: Some(actor_id),
+        kind: models::TransactionKind::Adjustment,
+        description: "",
+        amount_cents: total.saturating_abs(),
+        occurred_at: Utc::now(),
+    };
+
+    let detail = diesel::insert_into(txn::table)
+        .values(&new_transaction)
+        .get_result::<models::TransactionDetail>(conn)?;
+
+    let shares = split_evenly(total, others.len());
+    let mut new_parts = vec![models::NewTransactionPart {
+        txn_id: detail.id,
+        balance_id: balance.detail.id,
+        balance_change_cents: -total,
+    }];
+    new_parts.extend(
+        others
+            .into_iter()
+            .zip(shares)
+            .filter(|(_, change)| *change != 0)
+            .map(|(balance_id, change)| models::NewTransactionPart {
+                txn_id: detail.id,
+                balance_id,
+                balance_change_cents: change,
+            }),
+    );
+
+    diesel::insert_into(txn_part::table)
+        .values(new_parts)
+        .execute(conn)?;
+
+    Ok(models::Transaction { node, detail })
+}
+
+/// Split `total` into `n` shares which differ by at most one cent and add up
+/// to exactly `total`
+fn split_evenly(total: i32, n: usize) -> Vec<i32> {
+    let n = i32::try_from(n).expect("Too many balances to split between");
+    let (base, remainder) = (total / n, total % n);
+
+    (0..n)
+        .map(|i| {
+            if i < remainder.abs() {
+                base + remainder.signum()
+            } else {
+                base
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_evenly;
+
+    #[test]
+    fn test_split_evenly() {
+        assert_eq!(vec![3, 3, 3], split_evenly(9, 3));
+        assert_eq!(vec![4, 3, 3], split_evenly(10, 3));
+        assert_eq!(vec![-4, -4, -3], split_evenly(-11, 3));
+        assert_eq!(vec![1, 1, 0, 0], split_evenly(2, 4));
+        assert_eq!(vec![-7], split_evenly(-7, 1));
+    }
+}
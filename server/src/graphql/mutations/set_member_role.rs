@@ -0,0 +1,113 @@
+use super::super::{nodes::Balance, IdParser};
+use super::audit::Audit;
+use super::membership::{has_other_owner, require_membership, require_role};
+use crate::db::{
+    models,
+    schema::{balance, node, person, squad},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{FieldResult, ID};
+use diesel::{
+    prelude::*,
+    result::Error::{NotFound, RollbackTransaction},
+};
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct SetMemberRoleInput {
+    pub person_id: ID,
+    pub squad_id: ID,
+    pub role: models::MemberRole,
+}
+
+pub struct ParsedSetMemberRoleInput {
+    person_uid: Uuid,
+    squad_uid: Uuid,
+    role: models::MemberRole,
+}
+
+impl ParsedSetMemberRoleInput {
+    pub fn parse(
+        value: SetMemberRoleInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedSetMemberRoleInput> {
+        let person_uid = ids.parse(&value.person_id, models::NodeType::Person)?;
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
+
+        Ok(ParsedSetMemberRoleInput {
+            person_uid,
+            squad_uid,
+            role: value.role,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct SetMemberRolePayload {
+    pub balance: Balance,
+}
+
+/// Change a member's role on behalf of the signed-in person (identified by
+/// `viewer_id`), who must be one of the squad's owners or admins. Only owners
+/// may grant the owner role or change the role of another owner, and a squad
+/// always keeps at least one owner.
+pub async fn set_member_role(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedSetMemberRoleInput,
+    audit: Audit,
+) -> Result<SetMemberRolePayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
+                .get_result::<models::Squad>(conn)?;
+
+            let viewer_membership = require_role(
+                conn,
+                viewer_id,
+                squad.detail.id,
+                models::MemberRole::can_manage,
+            )?;
+
+            let person_id = person::table
+                .inner_join(node::table)
+                .filter(node::uid.eq(input.person_uid))
+                .select(person::id)
+                .get_result::<i32>(conn)?;
+
+            let membership = require_membership(conn, person_id, squad.detail.id)?;
+
+            let involves_owner = membership.role == models::MemberRole::Owner
+                || input.role == models::MemberRole::Owner;
+            if involves_owner && viewer_membership.role != models::MemberRole::Owner {
+                return Err(NotFound);
+            }
+
+            if membership.role == models::MemberRole::Owner
+                && input.role != models::MemberRole::Owner
+                && !has_other_owner(conn, squad.detail.id, membership.id)?
+            {
+                return Err(RollbackTransaction);
+            }
+
+            let detail = diesel::update(&membership)
+                .set(balance::role.eq(input.role))
+                .get_result::<models::BalanceDetail>(conn)?;
+
+            let node = node::table
+                .filter(node::id.eq(detail.node_id))
+                .get_result::<models::Node>(conn)?;
+
+            audit.record(conn, Some(squad.detail.id), vec![node.uid])?;
+
+            Ok(SetMemberRolePayload {
+                balance: models::Balance { node, detail }.into(),
+            })
+        })
+        .await?)
+}
@@ -0,0 +1,174 @@
+use super::super::{nodes::Person, IdParser};
+use super::audit::Audit;
+use super::conflict::Conflict;
+use crate::db::{
+    models,
+    schema::{node, person},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{validators::Email, FieldResult, ID};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct UpdatePersonInput {
+    pub person_id: ID,
+    #[graphql(validator(Email))]
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// Whether people who don't share a squad with the person can find them
+    /// with `searchPeople`
+    pub discoverable: Option<bool>,
+    /// Who may see the person's email address
+    pub email_visibility: Option<models::Visibility>,
+    /// Who may see the person's first and last names
+    pub name_visibility: Option<models::Visibility>,
+    /// The version of the person the change is based on. If they have been
+    /// changed since, the mutation fails with a CONFLICT error
+    pub expected_version: i32,
+}
+
+pub struct ParsedUpdatePersonInput {
+    person_uid: Uuid,
+    email: Option<String>,
+    display_name: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    discoverable: Option<bool>,
+    email_visibility: Option<models::Visibility>,
+    name_visibility: Option<models::Visibility>,
+    expected_version: i32,
+}
+
+impl ParsedUpdatePersonInput {
+    pub fn parse(value: UpdatePersonInput, ids: &IdParser) -> FieldResult<ParsedUpdatePersonInput> {
+        let person_uid = ids.parse(&value.person_id, models::NodeType::Person)?;
+
+        Ok(ParsedUpdatePersonInput {
+            person_uid,
+            email: value.email,
+            display_name: value.display_name,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            discoverable: value.discoverable,
+            email_visibility: value.email_visibility,
+            name_visibility: value.name_visibility,
+            expected_version: value.expected_version,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct UpdatePersonPayload {
+    pub person: Person,
+}
+
+/// Update the details of the signed-in person (identified by `viewer_id`),
+/// unless they have been changed since the version the change is based on.
+/// People may only update their own details.
+pub async fn update_person(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedUpdatePersonInput,
+    audit: Audit,
+) -> Result<UpdatePersonPayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let person = node::table
+                .inner_join(person::table)
+                .filter(node::uid.eq(input.person_uid))
+                .filter(person::id.eq(viewer_id))
+                .for_update()
+                .get_result::<models::Person>(conn)?;
+
+            if let Err(conflict) = Conflict::check(input.expected_version, person.detail.version) {
+                return Ok(Err(conflict));
+            }
+
+            let changes = models::PersonChanges {
+                email: input.email.as_deref(),
+                display_name: input.display_name.as_deref(),
+                first_name: input.first_name.as_deref(),
+                last_name: input.last_name.as_deref(),
+                discoverable: input.discoverable,
+                email_visibility: input.email_visibility,
+                name_visibility: input.name_visibility,
+            };
+
+            // Every mutation is recorded, even one which changes nothing
+            audit.record(conn, None, vec![person.node.uid])?;
+
+            if changes.email.is_none()
+                && changes.display_name.is_none()
+                && changes.first_name.is_none()
+                && changes.last_name.is_none()
+                && changes.discoverable.is_none()
+                && changes.email_visibility.is_none()
+                && changes.name_visibility.is_none()
+            {
+                return Ok(Ok(UpdatePersonPayload {
+                    person: person.into(),
+                }));
+            }
+
+            let node = person.node;
+            let detail = diesel::update(&person.detail)
+                .set((&changes, person::version.eq(person::version + 1)))
+                .get_result::<models::PersonDetail>(conn)?;
+
+            Ok(Ok(UpdatePersonPayload {
+                person: models::Person { node, detail }.into(),
+            }))
+        })
+        .await??)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{audit, person};
+    use super::*;
+    use crate::db::{schema::audit_log, tests::test_pool};
+    use serde_json::json;
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn test_no_change_is_recorded() {
+        let pool = test_pool();
+        let person_id = person(&pool).await;
+        let (person_uid, version) = node::table
+            .inner_join(person::table)
+            .filter(person::id.eq(person_id))
+            .select((node::uid, person::version))
+            .get_result_async::<(Uuid, i32)>(&pool)
+            .await
+            .unwrap();
+
+        let input = ParsedUpdatePersonInput {
+            person_uid,
+            email: None,
+            display_name: None,
+            first_name: None,
+            last_name: None,
+            discoverable: None,
+            email_visibility: None,
+            name_visibility: None,
+            expected_version: version,
+        };
+        let audit = audit("updatePerson", person_id, json!({}));
+        let updated = update_person(&pool, person_id, input, audit).await.unwrap();
+        assert_eq!(updated.person.model.detail.version, version);
+
+        let recorded = audit_log::table
+            .filter(audit_log::actor_id.eq(person_id))
+            .filter(audit_log::operation.eq("updatePerson"))
+            .select(audit_log::node_uids)
+            .get_results_async::<Vec<Uuid>>(&pool)
+            .await
+            .unwrap();
+        assert_eq!(recorded, vec![vec![person_uid]]);
+    }
+}
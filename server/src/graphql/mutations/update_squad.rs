@@ -0,0 +1,92 @@
+use super::super::{nodes::Squad, IdParser};
+use super::audit::Audit;
+use super::conflict::Conflict;
+use super::membership::require_role;
+use crate::db::{
+    models,
+    schema::{node, squad},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{FieldResult, ID};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct UpdateSquadInput {
+    pub squad_id: ID,
+    pub display_name: String,
+    /// The version of the squad the change is based on. If the squad has
+    /// been changed since, the mutation fails with a CONFLICT error
+    pub expected_version: i32,
+}
+
+pub struct ParsedUpdateSquadInput {
+    squad_uid: Uuid,
+    display_name: String,
+    expected_version: i32,
+}
+
+impl ParsedUpdateSquadInput {
+    pub fn parse(value: UpdateSquadInput, ids: &IdParser) -> FieldResult<ParsedUpdateSquadInput> {
+        let squad_uid = ids.parse(&value.squad_id, models::NodeType::Squad)?;
+
+        Ok(ParsedUpdateSquadInput {
+            squad_uid,
+            display_name: value.display_name,
+            expected_version: value.expected_version,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct UpdateSquadPayload {
+    pub squad: Squad,
+}
+
+/// Rename a squad on behalf of the signed-in person (identified by
+/// `viewer_id`), who must be one of its owners or admins, unless it has been
+/// changed since the version the change is based on
+pub async fn update_squad(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedUpdateSquadInput,
+    audit: Audit,
+) -> Result<UpdateSquadPayload> {
+    Ok(pool
+        .transaction(move |conn| {
+            let squad = node::table
+                .inner_join(squad::table)
+                .filter(node::uid.eq(input.squad_uid))
+                .filter(squad::archived.eq(false))
+                .for_update()
+                .get_result::<models::Squad>(conn)?;
+
+            require_role(
+                conn,
+                viewer_id,
+                squad.detail.id,
+                models::MemberRole::can_manage,
+            )?;
+
+            if let Err(conflict) = Conflict::check(input.expected_version, squad.detail.version) {
+                return Ok(Err(conflict));
+            }
+
+            let node = squad.node;
+            let detail = diesel::update(&squad.detail)
+                .set((
+                    squad::display_name.eq(&input.display_name),
+                    squad::version.eq(squad::version + 1),
+                ))
+                .get_result::<models::SquadDetail>(conn)?;
+
+            audit.record(conn, Some(detail.id), vec![node.uid])?;
+
+            Ok(Ok(UpdateSquadPayload {
+                squad: models::Squad { node, detail }.into(),
+            }))
+        })
+        .await??)
+}
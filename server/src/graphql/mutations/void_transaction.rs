@@ -0,0 +1,223 @@
+use super::super::{
+    nodes::{Squad, Transaction},
+    IdParser,
+};
+use super::audit::Audit;
+use super::conflict::Conflict;
+use super::idempotency::{replay_transaction, IdempotencyKey};
+use super::membership::require_role;
+use crate::db::{
+    models,
+    schema::{node, squad, txn},
+    traced::*,
+    Pool,
+};
+use anyhow::Result;
+use async_graphql::{validators::StringMaxLength, FieldResult, ID};
+use diesel::{prelude::*, result::Error::NotFound};
+use uuid::Uuid;
+
+#[derive(async_graphql::InputObject)]
+pub struct VoidTransactionInput {
+    pub transaction_id: ID,
+    /// The version of the transaction the change is based on. If it has been
+    /// changed since, the mutation fails with a CONFLICT error
+    pub expected_version: i32,
+    /// Identifies this mutation, so that if it is retried with the same ID the
+    /// voided transaction is returned instead of an error
+    #[graphql(validator(StringMaxLength(length = "255")))]
+    pub client_mutation_id: Option<String>,
+}
+
+pub struct ParsedVoidTransactionInput {
+    transaction_uid: Uuid,
+    expected_version: i32,
+    client_mutation_id: Option<String>,
+}
+
+impl ParsedVoidTransactionInput {
+    pub fn parse(
+        value: VoidTransactionInput,
+        ids: &IdParser,
+    ) -> FieldResult<ParsedVoidTransactionInput> {
+        let transaction_uid = ids.parse(&value.transaction_id, models::NodeType::Txn)?;
+
+        Ok(ParsedVoidTransactionInput {
+            transaction_uid,
+            expected_version: value.expected_version,
+            client_mutation_id: value.client_mutation_id,
+        })
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct VoidTransactionPayload {
+    pub client_mutation_id: Option<String>,
+    pub squad: Squad,
+    pub transaction: Transaction,
+}
+
+/// Void a transaction on behalf of the signed-in person (identified by
+/// `viewer_id`). Members may void transactions they recorded; only owners and
+/// admins may void anybody else's. Voided transactions are kept, but no
+/// longer count towards any balance. If the mutation is a retry of one
+/// already applied, the transaction voided then is returned. Fails with a
+/// `Conflict` if the transaction has been changed since the version the change
+/// is based on, in which case the mutation may be retried with the same key.
+pub async fn void_transaction(
+    pool: &Pool,
+    viewer_id: i32,
+    input: ParsedVoidTransactionInput,
+    audit: Audit,
+) -> Result<VoidTransactionPayload> {
+    let key = IdempotencyKey::new(viewer_id, &audit, input.client_mutation_id.clone());
+
+    pool.transaction(move |conn| {
+        let replayed = match &key {
+            Some(key) => match key.claim(conn)? {
+                Ok(replayed) => replayed,
+                Err(key_reused) => return Ok(Err(anyhow::Error::from(key_reused))),
+            },
+            None => None,
+        };
+        if let Some(node_id) = replayed {
+            let (squad, transaction) = replay_transaction(conn, node_id)?;
+            return Ok(Ok(VoidTransactionPayload {
+                client_mutation_id: input.client_mutation_id,
+                squad: squad.into(),
+                transaction: transaction.into(),
+            }));
+        }
+
+        let transaction = node::table
+            .inner_join(txn::table)
+            .filter(node::uid.eq(input.transaction_uid))
+            .filter(txn::voided.eq(false))
+            .for_update()
+            .get_result::<models::Transaction>(conn)?;
+
+        let squad = node::table
+            .inner_join(squad::table)
+            .filter(squad::id.eq(transaction.detail.squad_id))
+            .filter(squad::archived.eq(false))
+            .get_result::<models::Squad>(conn)?;
+
+        let membership = require_role(
+            conn,
+            viewer_id,
+            squad.detail.id,
+            models::MemberRole::can_contribute,
+        )?;
+
+        if transaction.detail.created_by != Some(viewer_id) && !membership.role.can_manage() {
+            return Err(NotFound);
+        }
+
+        if let Err(conflict) = Conflict::check(input.expected_version, transaction.detail.version) {
+            // Nothing was changed, so the client may retry with the same key
+            if let Some(key) = &key {
+                key.release(conn)?;
+            }
+            return Ok(Err(conflict.into()));
+        }
+
+        let node = transaction.node;
+        let detail = diesel::update(&transaction.detail)
+            .set((txn::voided.eq(true), txn::version.eq(txn::version + 1)))
+            .get_result::<models::TransactionDetail>(conn)?;
+
+        if let Some(key) = &key {
+            key.complete(conn, node.id)?;
+        }
+
+        audit.record(conn, Some(squad.detail.id), vec![node.uid])?;
+
+        Ok(Ok(VoidTransactionPayload {
+            client_mutation_id: input.client_mutation_id,
+            squad: squad.into(),
+            transaction: models::Transaction { node, detail }.into(),
+        }))
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        new_transaction,
+        tests::{audit, squad_of_two},
+        ParsedNewTransactionInput,
+    };
+    use super::*;
+    use crate::db::tests::test_pool;
+    use serde_json::json;
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn test_retry_after_conflict() {
+        let pool = test_pool();
+        let squad = squad_of_two(&pool).await;
+        let owner_id = squad.owner_id;
+        let input = ParsedNewTransactionInput {
+            squad_uid: squad.squad_uid,
+            balance_changes_detail: vec![
+                (squad.balance_uids[0], 100),
+                (squad.balance_uids[1], -100),
+            ]
+            .into_iter()
+            .collect(),
+            kind: models::TransactionKind::Expense,
+            description: String::new(),
+            occurred_at: None,
+            client_mutation_id: None,
+        };
+        let recorded = new_transaction(
+            &pool,
+            owner_id,
+            input,
+            audit("newTransaction", owner_id, json!({})),
+        )
+        .await
+        .unwrap()
+        .transaction
+        .model;
+
+        let void = |expected_version| {
+            let input = ParsedVoidTransactionInput {
+                transaction_uid: recorded.node.uid,
+                expected_version,
+                client_mutation_id: Some(String::from("void")),
+            };
+            let audit = audit(
+                "voidTransaction",
+                owner_id,
+                json!({ "expectedVersion": expected_version, "clientMutationId": "void" }),
+            );
+            void_transaction(&pool, owner_id, input, audit)
+        };
+
+        let conflict = void(recorded.detail.version - 1).await.err().unwrap();
+        assert!(
+            conflict.downcast_ref::<Conflict>().is_some(),
+            "{}",
+            conflict
+        );
+
+        // The conflict changed nothing, so the key may be used again
+        let voided = void(recorded.detail.version)
+            .await
+            .unwrap()
+            .transaction
+            .model;
+        assert_eq!(voided.node.id, recorded.node.id);
+        assert!(voided.detail.voided);
+
+        let replayed = void(recorded.detail.version)
+            .await
+            .unwrap()
+            .transaction
+            .model;
+        assert_eq!(replayed.node.id, recorded.node.id);
+        assert_eq!(replayed.detail.version, voided.detail.version);
+    }
+}
@@ -1,11 +1,16 @@
-use super::super::edges::BalanceTransactionConnection;
+use super::super::{edges::BalanceTransactionConnection, GlobalId, Viewer};
 use super::{Person, Squad};
-use crate::db::{models, schema::txn_part, Pool};
-use async_graphql::{Context, FieldError, FieldResult};
+use crate::db::{
+    models,
+    schema::{txn, txn_part},
+    traced::*,
+    Pool,
+};
+use async_graphql::{Context, FieldError, FieldResult, ID};
 use diesel::prelude::*;
 use std::convert::TryFrom;
-use tokio_diesel::*;
 
+#[derive(Clone)]
 pub struct Balance {
     pub model: models::Balance,
 }
@@ -18,22 +23,26 @@ impl From<models::Balance> for Balance {
 
 #[async_graphql::Object]
 impl Balance {
-    pub async fn id(&self) -> String {
-        self.model.node.uid.to_string()
+    pub async fn id(&self) -> ID {
+        GlobalId::from(&self.model.node).into()
     }
 
-    pub async fn total_cents(&self, context: &Context<'_>) -> FieldResult<i32> {
-        use diesel::dsl::sum;
+    /// Inactive balances belong to people who have left the squad. They are
+    /// kept so that the squad's transaction history remains intact.
+    pub async fn active(&self) -> bool {
+        self.model.detail.active
+    }
 
-        let sum = txn_part::table
-            .filter(txn_part::balance_id.eq(self.model.detail.id))
-            .select(sum(txn_part::balance_change_cents))
-            .get_result_async::<Option<i64>>(context.data::<Pool>().unwrap())
-            .await?;
+    /// What this member is allowed to do within the squad
+    pub async fn role(&self) -> models::MemberRole {
+        self.model.detail.role
+    }
 
-        Ok(sum
-            .map(|n| i32::try_from(n).expect("Exceeded maximum representable balance"))
-            .unwrap_or(0))
+    /// The sum of all changes to this balance, excluding voided transactions
+    pub async fn total_cents(&self, context: &Context<'_>) -> FieldResult<i32> {
+        Balance::total_cents_by_id(context.data::<Pool>().unwrap(), self.model.detail.id)
+            .await
+            .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 
     pub async fn person(&self, context: &Context<'_>) -> FieldResult<Person> {
@@ -42,8 +51,17 @@ impl Balance {
             .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 
+    /// Only visible to the squad's active members
     pub async fn squad(&self, context: &Context<'_>) -> FieldResult<Squad> {
-        Squad::by_id(context.data::<Pool>().unwrap(), self.model.detail.squad_id)
+        let pool = context.data::<Pool>().unwrap();
+        let squad_id = self.model.detail.squad_id;
+        match context.data::<Viewer>()?.is_member(pool, squad_id).await {
+            Ok(true) => {}
+            Ok(false) => return Err(FieldError::from("Only squad members may view the squad")),
+            Err(_) => return Err(FieldError::from("Internal error")),
+        }
+
+        Squad::by_id(pool, squad_id)
             .await
             .or_else(|_e| Err(FieldError::from("Internal error")))
     }
@@ -57,3 +75,22 @@ impl Balance {
             .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 }
+
+impl Balance {
+    /// The sum of all changes to a balance, excluding voided transactions
+    pub async fn total_cents_by_id(pool: &Pool, id: i32) -> AsyncResult<i32> {
+        use diesel::dsl::sum;
+
+        let sum = txn_part::table
+            .inner_join(txn::table)
+            .filter(txn_part::balance_id.eq(id))
+            .filter(txn::voided.eq(false))
+            .select(sum(txn_part::balance_change_cents))
+            .get_result_async::<Option<i64>>(pool)
+            .await?;
+
+        Ok(sum
+            .map(|n| i32::try_from(n).expect("Exceeded maximum representable balance"))
+            .unwrap_or(0))
+    }
+}
@@ -9,3 +9,92 @@ pub use node::*;
 pub use person::*;
 pub use squad::*;
 pub use transaction::*;
+
+#[cfg(test)]
+mod tests {
+    use super::super::{viewer::tests::person, Viewer};
+    use super::*;
+    use crate::db::{models, traced::TracedConnection, Pool};
+    use async_graphql::{value, EmptyMutation, EmptySubscription, Request, Schema};
+    use diesel::r2d2::ConnectionManager;
+    use uuid::Uuid;
+
+    /// Hands out a person, one of their balances and its squad without
+    /// looking them up, as if they had been reached from another squad
+    struct Root;
+
+    #[async_graphql::Object]
+    impl Root {
+        async fn person(&self) -> Person {
+            person(2).into()
+        }
+
+        async fn balance(&self) -> Balance {
+            models::Balance {
+                node: node(3, models::NodeType::Balance),
+                detail: models::BalanceDetail {
+                    id: 3,
+                    node_id: 3,
+                    person_id: 2,
+                    squad_id: 4,
+                    active: true,
+                    role: models::MemberRole::Member,
+                },
+            }
+            .into()
+        }
+
+        async fn squad(&self) -> Squad {
+            models::Squad {
+                node: node(4, models::NodeType::Squad),
+                detail: models::SquadDetail {
+                    id: 4,
+                    node_id: 4,
+                    display_name: String::from("squad4"),
+                    archived: false,
+                    version: 1,
+                },
+            }
+            .into()
+        }
+    }
+
+    fn node(id: i32, node_type: models::NodeType) -> models::Node {
+        models::Node {
+            id,
+            uid: Uuid::new_v4(),
+            node_type,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_non_member_cannot_reach_squad() {
+        // Somebody who belongs to no squads gets nothing from another
+        // person's balances or squads, so the database is never queried
+        let pool = Pool::builder().build_unchecked(ConnectionManager::<TracedConnection>::new(""));
+        let schema = Schema::new(Root, EmptyMutation, EmptySubscription);
+        let execute = |query: &str| {
+            schema.execute(
+                Request::new(query)
+                    .data(pool.clone())
+                    .data(Viewer::preloaded(person(1), &[], &[])),
+            )
+        };
+
+        let response = execute("{ person { balances { edges { node { id } } } } }").await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            value!({ "person": { "balances": { "edges": [] } } })
+        );
+
+        for query in &[
+            "{ balance { squad { id } } }",
+            "{ squad { balances { edges { node { id } } } } }",
+            "{ squad { transactions { edges { node { id } } } } }",
+        ] {
+            let response = execute(query).await;
+            assert_eq!(response.errors.len(), 1, "{}", query);
+        }
+    }
+}
@@ -1,15 +1,17 @@
+use super::super::Viewer;
 use super::{Balance, Person, Squad, Transaction};
 use crate::db::{
     models,
     schema::{balance, node, person, squad, txn},
+    traced::*,
     Pool,
 };
 use diesel::prelude::*;
-use tokio_diesel::*;
+use std::collections::HashMap;
 use uuid::Uuid;
 
-#[derive(async_graphql::Interface)]
-#[graphql(field(name = "id", type = "String"))]
+#[derive(Clone, async_graphql::Interface)]
+#[graphql(field(name = "id", type = "async_graphql::ID"))]
 pub enum Node {
     Person(Person),
     Squad(Squad),
@@ -18,50 +20,157 @@ pub enum Node {
 }
 
 impl Node {
-    pub async fn by_uid(pool: &Pool, uid: Uuid) -> AsyncResult<Node> {
+    fn model(&self) -> &models::Node {
+        match self {
+            Node::Person(person) => &person.model.node,
+            Node::Squad(squad) => &squad.model.node,
+            Node::Balance(balance) => &balance.model.node,
+            Node::Transaction(transaction) => &transaction.model.node,
+        }
+    }
+
+    /// Load the nodes with the given types and UUIDs, in the same order, with
+    /// None for any which do not exist or which the viewer may not see. The
+    /// type of a node may be unknown, e.g. if it is identified by a bare UUID.
+    /// Takes one query for each type of node requested, plus one to find out
+    /// the types of any unknown ones.
+    ///
+    /// Squads, and their balances and transactions, are only visible to their
+    /// active members. People are visible to themselves, their squad-mates,
+    /// and everybody if they are discoverable.
+    pub async fn by_ids(
+        pool: &Pool,
+        viewer: &Viewer,
+        ids: Vec<(Option<models::NodeType>, Uuid)>,
+    ) -> AsyncResult<Vec<Option<Node>>> {
+        let squads = viewer
+            .squads(pool)
+            .await?
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        let mut people = viewer
+            .squad_mates(pool)
+            .await?
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        people.extend(viewer.signed_in().map(|person| person.detail.id));
+
+        // Somebody who belongs to no squads can only see people, so there's
+        // no need to look for anything else
+        let ids = ids
+            .into_iter()
+            .map(|(node_type, uid)| match node_type {
+                Some(models::NodeType::Person) | None => Some((node_type, uid)),
+                Some(_) if !squads.is_empty() => Some((node_type, uid)),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+        if ids.iter().all(Option::is_none) {
+            return Ok(ids.into_iter().map(|_| None).collect());
+        }
+
         pool.transaction(move |conn| {
-            let node = node::table
-                .filter(node::uid.eq(uid))
-                .get_result::<models::Node>(conn)?;
+            let unknown = ids
+                .iter()
+                .flatten()
+                .filter(|(node_type, _)| node_type.is_none())
+                .map(|(_, uid)| *uid)
+                .collect::<Vec<_>>();
+            let found_types = if unknown.is_empty() {
+                HashMap::new()
+            } else {
+                node::table
+                    .filter(node::uid.eq_any(unknown))
+                    .select((node::uid, node::node_type))
+                    .get_results::<(Uuid, models::NodeType)>(conn)?
+                    .into_iter()
+                    .collect::<HashMap<_, _>>()
+            };
+
+            let ids = ids
+                .into_iter()
+                .map(|id| {
+                    id.map(|(node_type, uid)| {
+                        (node_type.or_else(|| found_types.get(&uid).copied()), uid)
+                    })
+                })
+                .collect::<Vec<_>>();
+            let uids_of_type = |node_type| {
+                ids.iter()
+                    .flatten()
+                    .filter(|(t, _)| *t == Some(node_type))
+                    .map(|(_, uid)| *uid)
+                    .collect::<Vec<_>>()
+            };
 
-            match node.node_type {
-                models::NodeType::Person => {
-                    let detail = person::table
-                        .filter(person::node_id.eq(node.id))
-                        .get_result::<models::PersonDetail>(conn)?;
+            let mut nodes = Vec::new();
 
-                    Ok(Node::Person(Person {
-                        model: models::Person { node, detail }.into(),
-                    }))
-                }
-                models::NodeType::Squad => {
-                    let detail = squad::table
-                        .filter(squad::node_id.eq(node.id))
-                        .get_result::<models::SquadDetail>(conn)?;
+            let uids = uids_of_type(models::NodeType::Person);
+            if !uids.is_empty() {
+                nodes.extend(
+                    node::table
+                        .inner_join(person::table)
+                        .filter(node::uid.eq_any(uids))
+                        .filter(person::id.eq_any(people).or(person::discoverable))
+                        .get_results::<models::Person>(conn)?
+                        .into_iter()
+                        .map(|model| Node::Person(model.into())),
+                );
+            }
 
-                    Ok(Node::Squad(Squad {
-                        model: models::Squad { node, detail }.into(),
-                    }))
-                }
-                models::NodeType::Balance => {
-                    let detail = balance::table
-                        .filter(balance::node_id.eq(node.id))
-                        .get_result::<models::BalanceDetail>(conn)?;
+            let uids = uids_of_type(models::NodeType::Squad);
+            if !uids.is_empty() {
+                nodes.extend(
+                    node::table
+                        .inner_join(squad::table)
+                        .filter(node::uid.eq_any(uids))
+                        .filter(squad::id.eq_any(squads.clone()))
+                        .get_results::<models::Squad>(conn)?
+                        .into_iter()
+                        .map(|model| Node::Squad(model.into())),
+                );
+            }
 
-                    Ok(Node::Balance(Balance {
-                        model: models::Balance { node, detail }.into(),
-                    }))
-                }
-                models::NodeType::Txn => {
-                    let detail = txn::table
-                        .filter(txn::node_id.eq(node.id))
-                        .get_result::<models::TransactionDetail>(conn)?;
+            let uids = uids_of_type(models::NodeType::Balance);
+            if !uids.is_empty() {
+                nodes.extend(
+                    node::table
+                        .inner_join(balance::table)
+                        .filter(node::uid.eq_any(uids))
+                        .filter(balance::squad_id.eq_any(squads.clone()))
+                        .get_results::<models::Balance>(conn)?
+                        .into_iter()
+                        .map(|model| Node::Balance(model.into())),
+                );
+            }
 
-                    Ok(Node::Transaction(Transaction {
-                        model: models::Transaction { node, detail }.into(),
-                    }))
-                }
+            let uids = uids_of_type(models::NodeType::Txn);
+            if !uids.is_empty() {
+                nodes.extend(
+                    node::table
+                        .inner_join(txn::table)
+                        .filter(node::uid.eq_any(uids))
+                        .filter(txn::squad_id.eq_any(squads))
+                        .get_results::<models::Transaction>(conn)?
+                        .into_iter()
+                        .map(|model| Node::Transaction(model.into())),
+                );
             }
+
+            let nodes = nodes
+                .into_iter()
+                .map(|node| ((node.model().node_type, node.model().uid), node))
+                .collect::<HashMap<_, _>>();
+
+            Ok(ids
+                .into_iter()
+                .map(|id| {
+                    let (node_type, uid) = id?;
+                    nodes.get(&(node_type?, uid)).cloned()
+                })
+                .collect())
         })
         .await
     }
@@ -1,13 +1,14 @@
-use super::super::edges::PersonBalanceConnection;
+use super::super::{edges::PersonBalanceConnection, GlobalId, Viewer};
 use crate::db::{
-    models,
+    models::{self, Visibility},
     schema::{node, person},
+    traced::*,
     Pool,
 };
-use async_graphql::{Context, FieldError, FieldResult};
+use async_graphql::{Context, FieldError, FieldResult, ID};
 use diesel::prelude::*;
-use tokio_diesel::*;
 
+#[derive(Clone)]
 pub struct Person {
     pub model: models::Person,
 }
@@ -20,31 +21,88 @@ impl From<models::Person> for Person {
 
 #[async_graphql::Object]
 impl Person {
-    pub async fn id(&self) -> String {
-        self.model.node.uid.to_string()
+    pub async fn id(&self) -> ID {
+        GlobalId::from(&self.model.node).into()
     }
 
-    pub async fn email(&self) -> &str {
-        &self.model.detail.email
+    /// The person's email address, or null if their `emailVisibility` hides
+    /// it from the viewer
+    pub async fn email(&self, context: &Context<'_>) -> FieldResult<Option<&str>> {
+        let visible = self
+            .is_visible(context, self.model.detail.email_visibility)
+            .await?;
+        Ok(Some(self.model.detail.email.as_str()).filter(|_| visible))
     }
     pub async fn display_name(&self) -> &str {
         &self.model.detail.display_name
     }
-    pub async fn first_name(&self) -> &str {
-        &self.model.detail.first_name
+    /// Null if the person's `nameVisibility` hides it from the viewer
+    pub async fn first_name(&self, context: &Context<'_>) -> FieldResult<Option<&str>> {
+        let visible = self
+            .is_visible(context, self.model.detail.name_visibility)
+            .await?;
+        Ok(Some(self.model.detail.first_name.as_str()).filter(|_| visible))
     }
-    pub async fn last_name(&self) -> &str {
-        &self.model.detail.last_name
+    /// Null if the person's `nameVisibility` hides it from the viewer
+    pub async fn last_name(&self, context: &Context<'_>) -> FieldResult<Option<&str>> {
+        let visible = self
+            .is_visible(context, self.model.detail.name_visibility)
+            .await?;
+        Ok(Some(self.model.detail.last_name.as_str()).filter(|_| visible))
     }
 
+    /// Who may see the person's email address. Only visible to the person
+    /// themselves.
+    pub async fn email_visibility(&self, context: &Context<'_>) -> FieldResult<Option<Visibility>> {
+        let visible = self.is_visible(context, Visibility::Private).await?;
+        Ok(Some(self.model.detail.email_visibility).filter(|_| visible))
+    }
+    /// Who may see the person's first and last names. Only visible to the
+    /// person themselves.
+    pub async fn name_visibility(&self, context: &Context<'_>) -> FieldResult<Option<Visibility>> {
+        let visible = self.is_visible(context, Visibility::Private).await?;
+        Ok(Some(self.model.detail.name_visibility).filter(|_| visible))
+    }
+
+    /// Whether people who don't share a squad with the person can find them
+    /// with `searchPeople`
+    pub async fn discoverable(&self) -> bool {
+        self.model.detail.discoverable
+    }
+
+    /// Incremented whenever the person's details are changed. Pass it to
+    /// `updatePerson` as the version the change is based on.
+    pub async fn version(&self) -> i32 {
+        self.model.detail.version
+    }
+
+    /// The person's balances in the squads the viewer is an active member of
     pub async fn balances(&self, context: &Context<'_>) -> FieldResult<PersonBalanceConnection> {
-        PersonBalanceConnection::by_person_id(context.data::<Pool>().unwrap(), self.model.detail.id)
-            .await
-            .or_else(|_e| Err(FieldError::from("Internal error")))
+        PersonBalanceConnection::by_person_id(
+            context.data::<Pool>().unwrap(),
+            context.data::<Viewer>()?,
+            self.model.detail.id,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 }
 
 impl Person {
+    /// Whether the viewer may see a detail of the person with the given
+    /// visibility
+    async fn is_visible(&self, context: &Context<'_>, visibility: Visibility) -> FieldResult<bool> {
+        context
+            .data::<Viewer>()?
+            .can_see(
+                context.data::<Pool>().unwrap(),
+                &self.model.detail,
+                visibility,
+            )
+            .await
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+
     pub async fn by_email(pool: &Pool, email: String) -> AsyncResult<Person> {
         node::table
             .inner_join(person::table)
@@ -1,13 +1,20 @@
-use super::super::edges::{SquadBalanceConnection, SquadTransactionConnection};
+use super::super::{
+    edges::{
+        OrderDirection, ParsedTransactionFilter, SquadAuditLogConnection, SquadBalanceConnection,
+        SquadTransactionConnection, TransactionCursor, TransactionFilter, TransactionOrderField,
+    },
+    GlobalId, IdParser, Viewer,
+};
 use crate::db::{
     models,
-    schema::{node, squad},
+    schema::{balance, node, squad},
+    traced::{OptionalExtension, *},
     Pool,
 };
-use async_graphql::{Context, FieldError, FieldResult};
+use async_graphql::{validators::IntRange, Context, FieldError, FieldResult, ID};
 use diesel::prelude::*;
-use tokio_diesel::*;
 
+#[derive(Clone)]
 pub struct Squad {
     pub model: models::Squad,
 }
@@ -20,27 +27,92 @@ impl From<models::Squad> for Squad {
 
 #[async_graphql::Object]
 impl Squad {
-    pub async fn id(&self) -> String {
-        self.model.node.uid.to_string()
+    pub async fn id(&self) -> ID {
+        GlobalId::from(&self.model.node).into()
     }
 
     pub async fn display_name(&self) -> &str {
         &self.model.detail.display_name
     }
 
+    /// Archived squads are read-only, but their history remains queryable
+    pub async fn archived(&self) -> bool {
+        self.model.detail.archived
+    }
+
+    /// Incremented whenever the squad is renamed or archived. Pass it to
+    /// `updateSquad` as the version the change is based on.
+    pub async fn version(&self) -> i32 {
+        self.model.detail.version
+    }
+
+    /// The signed-in person's role in this squad, or null if they are not an
+    /// active member
+    pub async fn my_role(&self, context: &Context<'_>) -> FieldResult<Option<models::MemberRole>> {
+        self.viewer_role(context).await
+    }
+
+    /// Every mutation which affected this squad, most recent first. Only
+    /// visible to the squad's owners and admins.
+    pub async fn audit_log(&self, context: &Context<'_>) -> FieldResult<SquadAuditLogConnection> {
+        match self.viewer_role(context).await? {
+            Some(role) if role.can_manage() => {}
+            _ => return Err(FieldError::from("Only squad admins may view the audit log")),
+        }
+
+        SquadAuditLogConnection::by_squad_id(context.data::<Pool>().unwrap(), self.model.detail.id)
+            .await
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+
+    /// Only visible to the squad's active members
     pub async fn balances(&self, context: &Context<'_>) -> FieldResult<SquadBalanceConnection> {
+        self.require_member(context).await?;
+
         SquadBalanceConnection::by_squad_id(context.data::<Pool>().unwrap(), self.model.detail.id)
             .await
             .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 
+    /// The squad's transactions which match `filter`, most recent first
+    /// unless sorted otherwise. All of them are returned unless `first` is
+    /// given; pass the `endCursor` of a page as `after` to get the next one.
+    /// Only visible to the squad's active members.
     pub async fn transactions(
         &self,
         context: &Context<'_>,
+        filter: Option<TransactionFilter>,
+        #[graphql(default_with = "TransactionOrderField::OccurredAt")] order_by: TransactionOrderField,
+        #[graphql(default_with = "OrderDirection::Desc")] direction: OrderDirection,
+        #[graphql(validator(IntRange(min = "1", max = "100")))] first: Option<i32>,
+        after: Option<String>,
     ) -> FieldResult<SquadTransactionConnection> {
-        SquadTransactionConnection::by_squad_id(context.data::<Pool>().unwrap(), self.model.detail.id)
-            .await
-            .or_else(|_e| Err(FieldError::from("Internal error")))
+        self.require_member(context).await?;
+
+        let ids = context.data::<IdParser>()?;
+        let filter = match filter {
+            Some(filter) => ParsedTransactionFilter::parse(filter, ids)?,
+            None => ParsedTransactionFilter::default(),
+        };
+        let after = match after {
+            Some(after) => Some(
+                TransactionCursor::decode(&after, order_by)
+                    .ok_or_else(|| FieldError::from("Invalid cursor"))?,
+            ),
+            None => None,
+        };
+
+        SquadTransactionConnection::by_squad_id(
+            context.data::<Pool>().unwrap(),
+            self.model.detail.id,
+            filter,
+            order_by,
+            direction,
+            first.map(i64::from),
+            after,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 }
 
@@ -53,4 +125,33 @@ impl Squad {
             .await
             .map(|squad| squad.into())
     }
+
+    /// An error unless the signed-in person is an active member of this squad
+    async fn require_member(&self, context: &Context<'_>) -> FieldResult<()> {
+        let viewer = context.data::<Viewer>()?;
+        match viewer.is_member(context.data::<Pool>().unwrap(), self.model.detail.id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(FieldError::from("Only squad members may view the squad's ledger")),
+            Err(_) => Err(FieldError::from("Internal error")),
+        }
+    }
+
+    /// The signed-in person's role in this squad, or None if they are not an
+    /// active member
+    async fn viewer_role(&self, context: &Context<'_>) -> FieldResult<Option<models::MemberRole>> {
+        let viewer = match context.data::<Viewer>()?.signed_in() {
+            Some(viewer) => viewer,
+            None => return Ok(None),
+        };
+
+        balance::table
+            .filter(balance::squad_id.eq(self.model.detail.id))
+            .filter(balance::person_id.eq(viewer.detail.id))
+            .filter(balance::active.eq(true))
+            .select(balance::role)
+            .get_result_async::<models::MemberRole>(context.data::<Pool>().unwrap())
+            .await
+            .optional()
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
 }
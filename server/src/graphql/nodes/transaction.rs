@@ -1,7 +1,12 @@
-use super::{super::edges::TransactionBalanceConnection, Squad};
+use super::{
+    super::{edges::TransactionBalanceConnection, GlobalId},
+    Person, Squad,
+};
 use crate::db::{models, Pool};
-use async_graphql::{Context, FieldError, FieldResult};
+use async_graphql::{Context, FieldError, FieldResult, ID};
+use chrono::{DateTime, Utc};
 
+#[derive(Clone)]
 pub struct Transaction {
     pub model: models::Transaction,
 }
@@ -14,8 +19,53 @@ impl From<models::Transaction> for Transaction {
 
 #[async_graphql::Object]
 impl Transaction {
-    pub async fn id(&self) -> String {
-        self.model.node.uid.to_string()
+    pub async fn id(&self) -> ID {
+        GlobalId::from(&self.model.node).into()
+    }
+
+    pub async fn kind(&self) -> models::TransactionKind {
+        self.model.detail.kind
+    }
+
+    pub async fn description(&self) -> &str {
+        &self.model.detail.description
+    }
+
+    /// The total of the transaction's positive balance changes
+    pub async fn amount_cents(&self) -> i32 {
+        self.model.detail.amount_cents
+    }
+
+    /// When the transaction happened, which may be before it was recorded
+    pub async fn occurred_at(&self) -> DateTime<Utc> {
+        self.model.detail.occurred_at
+    }
+
+    /// When the transaction was recorded
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.model.detail.created_at
+    }
+
+    /// Voided transactions no longer count towards any balance
+    pub async fn voided(&self) -> bool {
+        self.model.detail.voided
+    }
+
+    /// Incremented whenever the transaction is changed. Pass it to
+    /// `voidTransaction` as the version the change is based on.
+    pub async fn version(&self) -> i32 {
+        self.model.detail.version
+    }
+
+    /// The person who recorded this transaction, if they are still known
+    pub async fn created_by(&self, context: &Context<'_>) -> FieldResult<Option<Person>> {
+        match self.model.detail.created_by {
+            Some(id) => Person::by_id(context.data::<Pool>().unwrap(), id)
+                .await
+                .map(Some)
+                .or_else(|_e| Err(FieldError::from("Internal error"))),
+            None => Ok(None),
+        }
     }
 
     pub async fn squad(&self, context: &Context<'_>) -> FieldResult<Squad> {
@@ -0,0 +1,371 @@
+//! Persisted queries, which let clients send the SHA-256 hash of a query in
+//! place of its text. In `apq` mode, clients register queries as they go, using
+//! Apollo's automatic persisted queries protocol; a query is only kept once it
+//! has been parsed and validated. In `allowlist` mode, only the operations in a
+//! manifest generated by the Relay compiler may be executed.
+
+use crate::db::{
+    models,
+    schema::persisted_query,
+    traced::{OptionalExtension, *},
+    Pool,
+};
+use anyhow::{bail, Context as _, Result};
+use async_graphql::extensions::apollo_persisted_queries::{CacheStorage, LruCacheStorage};
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory};
+use async_graphql::{from_value, Request, ServerError, ServerResult};
+use diesel::prelude::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs, sync::Arc};
+
+/// Config parameters pertaining to persisted queries
+#[derive(Clone, Deserialize)]
+pub struct PersistedQuerySettings {
+    pub mode: String,
+    pub store: String,
+    pub cache_size: usize,
+    pub max_stored: i64,
+    pub manifest: String,
+}
+
+/// How persisted queries are handled. Clones share the same store or
+/// manifest, so that every worker's schema sees the same queries.
+#[derive(Clone)]
+pub enum PersistedQueries {
+    Off,
+    Apq(Store),
+    Allowlist(Allowlist),
+}
+
+impl PersistedQueries {
+    pub fn new(settings: &PersistedQuerySettings, pool: Pool) -> Result<PersistedQueries> {
+        match settings.mode.as_str() {
+            "off" => Ok(PersistedQueries::Off),
+            "apq" => {
+                if settings.cache_size == 0 {
+                    bail!("persisted_queries.cache_size must be greater than zero");
+                }
+                let pool = match settings.store.as_str() {
+                    "memory" => None,
+                    "database" => Some(pool),
+                    store => bail!(
+                        "persisted_queries.store must be memory or database, not '{}'",
+                        store
+                    ),
+                };
+                Ok(PersistedQueries::Apq(Store {
+                    cache: LruCacheStorage::new(settings.cache_size),
+                    pool,
+                    max_stored: settings.max_stored,
+                }))
+            }
+            "allowlist" => Ok(PersistedQueries::Allowlist(Allowlist::load(
+                &settings.manifest,
+            )?)),
+            mode => bail!(
+                "persisted_queries.mode must be off, apq or allowlist, not '{}'",
+                mode
+            ),
+        }
+    }
+}
+
+fn sha256_hex(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
+
+/// Queries registered through APQ, cached in memory and optionally stored in
+/// the database so that they are shared between servers and survive restarts
+#[derive(Clone)]
+pub struct Store {
+    cache: LruCacheStorage,
+    pool: Option<Pool>,
+    max_stored: i64,
+}
+
+impl Store {
+    async fn get(&self, key: String) -> Option<String> {
+        if let Some(query) = self.cache.get(key.clone()).await {
+            return Some(query);
+        }
+
+        let result = persisted_query::table
+            .find(key.clone())
+            .select(persisted_query::query)
+            .get_result_async::<String>(self.pool.as_ref()?)
+            .await
+            .optional();
+        match result {
+            Ok(Some(query)) => {
+                self.cache.set(key, query.clone()).await;
+                Some(query)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                log::error!("Failed to look up persisted query: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Keep a query which has been parsed and validated. Once the table holds
+    /// `max_stored` queries, new ones are only cached in memory.
+    async fn set(&self, key: String, query: String) {
+        if let Some(pool) = &self.pool {
+            let (sha256_hash, text, max_stored) = (key.clone(), query.clone(), self.max_stored);
+            let result = pool
+                .transaction(move |conn| {
+                    let stored = persisted_query::table.count().get_result::<i64>(conn)?;
+                    if stored >= max_stored {
+                        return Ok(0);
+                    }
+                    diesel::insert_into(persisted_query::table)
+                        .values(&models::NewPersistedQuery {
+                            sha256_hash: &sha256_hash,
+                            query: &text,
+                        })
+                        .on_conflict_do_nothing()
+                        .execute(conn)
+                })
+                .await;
+            if let Err(e) = result {
+                log::error!("Failed to store persisted query: {}", e);
+            }
+        }
+
+        self.cache.set(key, query).await;
+    }
+}
+
+impl ExtensionFactory for Store {
+    fn create(&self) -> Box<dyn Extension> {
+        Box::new(Apq {
+            store: self.clone(),
+            registered: None,
+        })
+    }
+}
+
+/// Implements the automatic persisted queries protocol for one request
+struct Apq {
+    store: Store,
+    /// The hash and text of a query the request is registering, kept until
+    /// the query is known to be valid
+    registered: Option<(String, String)>,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for Apq {
+    /// Replace the hash of a known query with its text. A request with both
+    /// the hash and the text registers the query.
+    async fn prepare_request(
+        &mut self,
+        _ctx: &ExtensionContext<'_>,
+        mut request: Request,
+    ) -> ServerResult<Request> {
+        let persisted_query = match request.extensions.remove("persistedQuery") {
+            Some(value) => match from_value::<PersistedQuery>(value) {
+                Ok(persisted_query) if persisted_query.version == Some(1) => persisted_query,
+                _ => return Err(ServerError::new("Invalid persistedQuery extension")),
+            },
+            None => return Ok(request),
+        };
+
+        if request.query.is_empty() {
+            match self.store.get(persisted_query.sha256_hash).await {
+                Some(query) => {
+                    request.query = query;
+                    Ok(request)
+                }
+                // Apollo clients look for exactly this message
+                None => Err(ServerError::new("PersistedQueryNotFound")),
+            }
+        } else if sha256_hex(&request.query) == persisted_query.sha256_hash {
+            self.registered = Some((persisted_query.sha256_hash, request.query.clone()));
+            Ok(request)
+        } else {
+            Err(ServerError::new("provided sha does not match query"))
+        }
+    }
+
+    /// Only reached once the query has been parsed, validated and found to be
+    /// within the schema's limits, so that nothing else is ever kept
+    fn execution_start(&mut self, _ctx: &ExtensionContext<'_>) {
+        if let Some((key, query)) = self.registered.take() {
+            let store = self.store.clone();
+            actix_rt::spawn(async move { store.set(key, query).await });
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PersistedQuery {
+    /// Sent by Apollo clients, which only use version 1, but not by Relay
+    version: Option<i32>,
+    #[serde(rename = "sha256Hash")]
+    sha256_hash: String,
+}
+
+/// The operations which may be executed in `allowlist` mode, keyed both by
+/// their IDs in the manifest and by the SHA-256 hashes of their text
+#[derive(Clone)]
+pub struct Allowlist(Arc<HashMap<String, String>>);
+
+impl Allowlist {
+    /// Load a manifest written by `relay-compiler --persist-output`, which maps
+    /// the ID of each operation to its text
+    fn load(path: &str) -> Result<Allowlist> {
+        if path.is_empty() {
+            bail!("persisted_queries.manifest must be set in allowlist mode");
+        }
+
+        let manifest = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read persisted query manifest '{}'", path))?;
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest)
+            .with_context(|| format!("'{}' is not a persisted query manifest", path))?;
+
+        let mut queries = HashMap::with_capacity(manifest.len() * 2);
+        for (id, query) in manifest {
+            queries.insert(sha256_hex(&query), query.clone());
+            queries.insert(id, query);
+        }
+
+        log::info!("Only executing persisted queries from {}", path);
+        Ok(Allowlist(Arc::new(queries)))
+    }
+}
+
+impl ExtensionFactory for Allowlist {
+    fn create(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for Allowlist {
+    /// Replace the hash of an allowed query with its text, or reject the
+    /// request. Requests may also include the text of an allowed query
+    /// verbatim.
+    async fn prepare_request(
+        &mut self,
+        _ctx: &ExtensionContext<'_>,
+        mut request: Request,
+    ) -> ServerResult<Request> {
+        let persisted_query = request.extensions.remove("persistedQuery");
+        let key = if !request.query.is_empty() {
+            sha256_hex(&request.query)
+        } else if let Some(value) = persisted_query {
+            match from_value::<PersistedQuery>(value) {
+                Ok(persisted_query) => persisted_query.sha256_hash,
+                Err(_) => return Err(ServerError::new("Invalid persistedQuery extension")),
+            }
+        } else {
+            String::new()
+        };
+
+        match self.0.get(&key) {
+            Some(query) => {
+                request.query = query.clone();
+                Ok(request)
+            }
+            None => Err(ServerError::new("Only persisted queries may be executed")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::schema_builder;
+    use super::*;
+    use async_graphql::value;
+    use std::time::Duration;
+
+    fn by_hash(hash: &str) -> Request {
+        let mut request = Request::new("");
+        request.extensions.insert(
+            "persistedQuery".to_string(),
+            value!({ "version": 1, "sha256Hash": hash }),
+        );
+        request
+    }
+
+    fn registering(query: &str) -> Request {
+        let mut request = by_hash(&sha256_hex(query));
+        request.query = query.to_string();
+        request
+    }
+
+    #[actix_rt::test]
+    async fn test_apq() {
+        let store = Store {
+            cache: LruCacheStorage::new(10),
+            pool: None,
+            max_stored: 0,
+        };
+        let schema = schema_builder().extension(store).finish();
+        let valid = "query Typename { __typename }";
+
+        // Queries which fail to parse or validate are never kept
+        for query in [valid, "query Broken {", "{ nonexistent }"] {
+            let response = schema.execute(by_hash(&sha256_hex(query))).await;
+            assert_eq!(response.errors[0].message, "PersistedQueryNotFound");
+
+            schema.execute(registering(query)).await;
+            // Let the spawned task which stores the query run
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+
+        let response = schema.execute(by_hash(&sha256_hex(valid))).await;
+        assert_eq!(response.data, value!({ "__typename": "QueryRoot" }));
+        for query in ["query Broken {", "{ nonexistent }"] {
+            let response = schema.execute(by_hash(&sha256_hex(query))).await;
+            assert_eq!(response.errors[0].message, "PersistedQueryNotFound");
+        }
+
+        let mut mismatched = registering(valid);
+        mismatched.query = "{ __typename }".to_string();
+        let response = schema.execute(mismatched).await;
+        assert_eq!(
+            response.errors[0].message,
+            "provided sha does not match query"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_allowlist() {
+        let query = "query Typename { __typename }";
+        let allowlist = Allowlist(Arc::new(
+            vec![
+                ("relay-id".to_string(), query.to_string()),
+                (sha256_hex(query), query.to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        ));
+        let schema = schema_builder().extension(allowlist).finish();
+
+        // Allowed queries may be sent by ID, by hash, or in full
+        for request in [
+            by_hash("relay-id"),
+            by_hash(&sha256_hex(query)),
+            Request::new(query),
+        ] {
+            let response = schema.execute(request).await;
+            assert_eq!(response.data, value!({ "__typename": "QueryRoot" }));
+        }
+
+        // Anything else is rejected
+        for request in [
+            by_hash("unknown"),
+            Request::new("{ __typename }"),
+            Request::new(""),
+        ] {
+            let response = schema.execute(request).await;
+            assert_eq!(
+                response.errors[0].message,
+                "Only persisted queries may be executed"
+            );
+        }
+    }
+}
@@ -1,32 +1,210 @@
-use super::nodes::{Node, Person};
+use super::{
+    edges::{decode_cursor, PersonSearchConnection},
+    nodes::{Node, Person},
+    IdParser, Invite, Viewer,
+};
 use crate::db::Pool;
-use async_graphql::{validators::Email, Context, FieldError, FieldResult, ID};
-use uuid::Uuid;
+use async_graphql::{
+    validators::{Email, IntRange, ListMaxLength, StringMaxLength, StringMinLength},
+    Context, FieldError, FieldResult, ID,
+};
 
 /// Schema entry-point for queries
 pub struct QueryRoot;
 
 #[async_graphql::Object]
 impl QueryRoot {
+    /// The signed-in person, if any
+    pub async fn viewer(&self, context: &Context<'_>) -> FieldResult<Option<Person>> {
+        let viewer = match context.data::<Viewer>()?.signed_in() {
+            Some(viewer) => viewer,
+            None => return Ok(None),
+        };
+
+        Person::by_id(context.data::<Pool>().unwrap(), viewer.detail.id)
+            .await
+            .map(Some)
+            .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+
+    /// Invites addressed to the signed-in person's email which they have not
+    /// yet accepted
+    pub async fn pending_invites(&self, context: &Context<'_>) -> FieldResult<Vec<Invite>> {
+        let viewer = context.data::<Viewer>()?.person()?;
+
+        Invite::pending_for_email(
+            context.data::<Pool>().unwrap(),
+            viewer.detail.id,
+            viewer.detail.email.clone(),
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Internal error")))
+    }
+
+    /// The person with the given email address. Only found if their
+    /// `emailVisibility` lets the viewer see it, so that the query can't be
+    /// used to find out who has an account.
     pub async fn person_by_email(
         &self,
         context: &Context<'_>,
         #[graphql(validator(Email))] email: String,
     ) -> FieldResult<Person> {
-        Person::by_email(context.data::<Pool>().unwrap(), email)
+        let pool = context.data::<Pool>().unwrap();
+        let viewer = context.data::<Viewer>()?;
+
+        match Person::by_email(pool, email).await {
+            Ok(person) => {
+                let detail = &person.model.detail;
+                match viewer.can_see(pool, detail, detail.email_visibility).await {
+                    Ok(true) => Ok(person),
+                    Ok(false) => Err(FieldError::from(
+                        "Could not find a person with the given email",
+                    )),
+                    Err(_) => Err(FieldError::from("Internal error")),
+                }
+            }
+            Err(_) => Err(FieldError::from(
+                "Could not find a person with the given email",
+            )),
+        }
+    }
+
+    /// People whose display name or full name contains `query`, or whose
+    /// email address starts with it, best matches first. Only people who
+    /// share a squad with the signed-in person, or who have chosen to be
+    /// discoverable, are found.
+    pub async fn search_people(
+        &self,
+        context: &Context<'_>,
+        #[graphql(validator(and(
+            StringMinLength(length = "2"),
+            StringMaxLength(length = "100")
+        )))]
+        query: String,
+        #[graphql(default = 10, validator(IntRange(min = "1", max = "50")))] first: i32,
+        after: Option<String>,
+    ) -> FieldResult<PersonSearchConnection> {
+        let pool = context.data::<Pool>().unwrap();
+        let viewer = context.data::<Viewer>()?;
+        let viewer_id = viewer.person()?.detail.id;
+        let squad_mates = viewer
+            .squad_mates(pool)
             .await
-            .or_else(|_e| {
-                Err(FieldError::from(
-                    "Could not find a person with the given email",
-                ))
-            })
+            .or_else(|_e| Err(FieldError::from("Internal error")))?;
+        let offset = match after {
+            Some(after) => {
+                decode_cursor(&after).ok_or_else(|| FieldError::from("Invalid cursor"))?
+            }
+            None => 0,
+        };
+
+        PersonSearchConnection::search(
+            pool,
+            viewer_id,
+            squad_mates.iter().copied().collect(),
+            query,
+            first.into(),
+            offset,
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Internal error")))
     }
 
     pub async fn node(&self, context: &Context<'_>, id: ID) -> FieldResult<Node> {
-        let uid = Uuid::parse_str(&id).or_else(|_e| Err(FieldError::from("Invalid ID")))?;
+        let id = context.data::<IdParser>()?.parse_any(&id)?;
 
-        Node::by_uid(context.data::<Pool>().unwrap(), uid)
-            .await
-            .or_else(|_e| Err(FieldError::from("Could not find a node with the given id")))
+        Node::by_ids(
+            context.data::<Pool>().unwrap(),
+            context.data::<Viewer>()?,
+            vec![id],
+        )
+        .await
+        .ok()
+        .and_then(|mut nodes| nodes.pop().flatten())
+        .ok_or_else(|| FieldError::from("Could not find a node with the given id"))
+    }
+
+    /// The nodes with the given IDs, in the same order, with null in place of
+    /// any which are invalid, cannot be found, or which the viewer may not see
+    #[graphql(complexity = "ids.len() * child_complexity")]
+    pub async fn nodes(
+        &self,
+        context: &Context<'_>,
+        #[graphql(validator(ListMaxLength(length = "100")))] ids: Vec<ID>,
+    ) -> FieldResult<Vec<Option<Node>>> {
+        let parser = context.data::<IdParser>()?;
+        let ids = ids
+            .iter()
+            .map(|id| parser.parse_any(id).ok())
+            .collect::<Vec<_>>();
+
+        let mut nodes = Node::by_ids(
+            context.data::<Pool>().unwrap(),
+            context.data::<Viewer>()?,
+            ids.iter().flatten().copied().collect(),
+        )
+        .await
+        .or_else(|_e| Err(FieldError::from("Internal error")))?
+        .into_iter();
+
+        Ok(ids
+            .iter()
+            .map(|id| id.and_then(|_| nodes.next().flatten()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{schema_builder, viewer::tests::person, GlobalId, GraphQLSettings};
+    use super::*;
+    use crate::db::models::NodeType;
+    use crate::db::traced::TracedConnection;
+    use async_graphql::{value, Request};
+    use diesel::r2d2::ConnectionManager;
+    use uuid::Uuid;
+
+    #[actix_rt::test]
+    async fn test_non_member_gets_null() {
+        // Somebody who belongs to no squads can't see any squad, balance or
+        // transaction, so the database is never queried
+        let pool = Pool::builder().build_unchecked(ConnectionManager::<TracedConnection>::new(""));
+        let schema = schema_builder()
+            .data(IdParser::new(&GraphQLSettings {
+                max_depth: 0,
+                max_complexity: 0,
+                accept_raw_uuids: false,
+            }))
+            .finish();
+        let squad_id = GlobalId::new(NodeType::Squad, Uuid::new_v4());
+        let balance_id = GlobalId::new(NodeType::Balance, Uuid::new_v4());
+        let txn_id = GlobalId::new(NodeType::Txn, Uuid::new_v4());
+
+        let query = format!(
+            r#"{{ nodes(ids: ["{}", "{}", "{}"]) {{ id }} }}"#,
+            squad_id, balance_id, txn_id
+        );
+        let response = schema
+            .execute(
+                Request::new(query)
+                    .data(pool.clone())
+                    .data(Viewer::preloaded(person(1), &[], &[])),
+            )
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.data, value!({ "nodes": [null, null, null] }));
+
+        let query = format!(r#"{{ node(id: "{}") {{ id }} }}"#, squad_id);
+        let response = schema
+            .execute(
+                Request::new(query)
+                    .data(pool)
+                    .data(Viewer::preloaded(person(1), &[], &[])),
+            )
+            .await;
+        assert_eq!(
+            response.errors[0].message,
+            "Could not find a node with the given id"
+        );
     }
 }
@@ -0,0 +1,187 @@
+use super::nodes::Person;
+use crate::db::{
+    models::{self, Visibility},
+    schema::balance,
+    traced::*,
+    Pool,
+};
+use async_graphql::{FieldError, FieldResult};
+use diesel::prelude::*;
+use futures::lock::Mutex;
+use std::{collections::HashSet, sync::Arc};
+
+/// The signed-in person on whose behalf a request is executed, if any
+pub struct Viewer {
+    person: Option<models::Person>,
+    /// The IDs of the squads the viewer is an active member of, loaded the
+    /// first time they are needed
+    squads: Mutex<Option<Arc<HashSet<i32>>>>,
+    /// The IDs of the people who share a squad with the viewer, loaded the
+    /// first time they are needed
+    squad_mates: Mutex<Option<Arc<HashSet<i32>>>>,
+}
+
+impl Viewer {
+    /// Resolve the viewer from the email remembered in their session
+    pub async fn from_identity(pool: &Pool, identity: Option<String>) -> Viewer {
+        let person = match identity {
+            Some(email) => Person::by_email(pool, email).await.ok().map(|p| p.model),
+            None => None,
+        };
+
+        Viewer {
+            person,
+            squads: Mutex::new(None),
+            squad_mates: Mutex::new(None),
+        }
+    }
+
+    /// A viewer whose squads and squad-mates are already known, so that tests
+    /// needn't query the database for them
+    #[cfg(test)]
+    pub fn preloaded(person: models::Person, squads: &[i32], squad_mates: &[i32]) -> Viewer {
+        Viewer {
+            person: Some(person),
+            squads: Mutex::new(Some(Arc::new(squads.iter().copied().collect()))),
+            squad_mates: Mutex::new(Some(Arc::new(squad_mates.iter().copied().collect()))),
+        }
+    }
+
+    /// The signed-in person, if any
+    pub fn signed_in(&self) -> Option<&models::Person> {
+        self.person.as_ref()
+    }
+
+    /// The signed-in person, or an error if the request is anonymous
+    pub fn person(&self) -> FieldResult<&models::Person> {
+        self.person
+            .as_ref()
+            .ok_or_else(|| FieldError::from("You must be signed in"))
+    }
+
+    /// The IDs of the squads the viewer is an active member of
+    pub async fn squads(&self, pool: &Pool) -> AsyncResult<Arc<HashSet<i32>>> {
+        let viewer_id = match &self.person {
+            Some(person) => person.detail.id,
+            None => return Ok(Arc::new(HashSet::new())),
+        };
+
+        let mut squads = self.squads.lock().await;
+        if let Some(squads) = &*squads {
+            return Ok(squads.clone());
+        }
+
+        let ids = balance::table
+            .select(balance::squad_id)
+            .filter(balance::person_id.eq(viewer_id))
+            .filter(balance::active)
+            .get_results_async::<i32>(pool)
+            .await?;
+
+        Ok(squads
+            .get_or_insert_with(|| Arc::new(ids.into_iter().collect()))
+            .clone())
+    }
+
+    /// Whether the viewer is an active member of the squad
+    pub async fn is_member(&self, pool: &Pool, squad_id: i32) -> AsyncResult<bool> {
+        Ok(self.squads(pool).await?.contains(&squad_id))
+    }
+
+    /// The IDs of the people who are active members of any squad the viewer is
+    /// an active member of
+    pub async fn squad_mates(&self, pool: &Pool) -> AsyncResult<Arc<HashSet<i32>>> {
+        if self.person.is_none() {
+            return Ok(Arc::new(HashSet::new()));
+        }
+
+        let mut squad_mates = self.squad_mates.lock().await;
+        if let Some(squad_mates) = &*squad_mates {
+            return Ok(squad_mates.clone());
+        }
+
+        let viewer_squads = self.squads(pool).await?;
+        let ids = balance::table
+            .select(balance::person_id)
+            .filter(balance::squad_id.eq_any(viewer_squads.iter().copied().collect::<Vec<_>>()))
+            .filter(balance::active)
+            .get_results_async::<i32>(pool)
+            .await?;
+
+        Ok(squad_mates
+            .get_or_insert_with(|| Arc::new(ids.into_iter().collect()))
+            .clone())
+    }
+
+    /// Whether the viewer may see a detail of `person` with the given
+    /// visibility
+    pub async fn can_see(
+        &self,
+        pool: &Pool,
+        person: &models::PersonDetail,
+        visibility: Visibility,
+    ) -> AsyncResult<bool> {
+        match (visibility, &self.person) {
+            (Visibility::Public, _) => Ok(true),
+            (_, None) => Ok(false),
+            (_, Some(viewer)) if viewer.detail.id == person.id => Ok(true),
+            (Visibility::Private, _) => Ok(false),
+            (Visibility::SquadMates, _) => Ok(self.squad_mates(pool).await?.contains(&person.id)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use diesel::r2d2::ConnectionManager;
+    use uuid::Uuid;
+
+    pub fn person(id: i32) -> models::Person {
+        models::Person {
+            node: models::Node {
+                id,
+                uid: Uuid::new_v4(),
+                node_type: models::NodeType::Person,
+            },
+            detail: models::PersonDetail {
+                id,
+                node_id: id,
+                display_name: format!("person{}", id),
+                email: format!("person{}@example.com", id),
+                first_name: String::new(),
+                last_name: String::new(),
+                version: 1,
+                discoverable: false,
+                email_visibility: Visibility::SquadMates,
+                name_visibility: Visibility::SquadMates,
+            },
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_can_see() {
+        // Squad-mates are preloaded, so the database is never queried
+        let pool = Pool::builder().build_unchecked(ConnectionManager::<TracedConnection>::new(""));
+        let viewer = Viewer::preloaded(person(1), &[1], &[1, 2]);
+        let anonymous = Viewer {
+            person: None,
+            squads: Mutex::new(None),
+            squad_mates: Mutex::new(None),
+        };
+
+        let (me, squad_mate, stranger) = (person(1), person(2), person(3));
+        for &(viewer, person, visibility, expected) in &[
+            (&viewer, &me, Visibility::Private, true),
+            (&viewer, &squad_mate, Visibility::Private, false),
+            (&viewer, &squad_mate, Visibility::SquadMates, true),
+            (&viewer, &stranger, Visibility::SquadMates, false),
+            (&viewer, &stranger, Visibility::Public, true),
+            (&anonymous, &me, Visibility::SquadMates, false),
+            (&anonymous, &me, Visibility::Public, true),
+        ] {
+            let can_see = viewer.can_see(&pool, &person.detail, visibility).await;
+            assert_eq!(can_see.unwrap(), expected, "{:?}", visibility);
+        }
+    }
+}
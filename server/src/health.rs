@@ -0,0 +1,114 @@
+//! Endpoints which let an orchestrator check on the server. `/healthz` only
+//! reports that the process is up and serving requests, while `/readyz`
+//! reports whether it is able to do useful work.
+
+use crate::db::{self, Pool};
+use crate::googlesignin::{CacheStatus, GoogleSignInClient};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: Check,
+    migrations: Check,
+    google_certs: CacheStatus,
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+impl Check {
+    fn ok() -> Check {
+        Check {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn failed(error: &'static str) -> Check {
+        Check {
+            ok: false,
+            error: Some(error),
+        }
+    }
+}
+
+/// Handler which succeeds whenever the process is able to serve requests
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Health { status: "ok" })
+}
+
+/// Handler which succeeds only if a database connection can be checked out
+/// and queried, and the database has no pending migrations. The state of the
+/// Google certificate cache is reported but does not affect readiness, as the
+/// certificates are only fetched when someone first signs in.
+pub async fn readyz(
+    pool: web::Data<Pool>,
+    gsi_client: web::Data<GoogleSignInClient>,
+) -> HttpResponse {
+    let (database, migrations) = match web::block(move || check_database(&pool)).await {
+        Ok(false) => (Check::ok(), Check::ok()),
+        Ok(true) => (
+            Check::ok(),
+            Check::failed("Database has pending migrations"),
+        ),
+        Err(e) => {
+            log::error!("Readiness check failed: {:?}", e);
+            (
+                Check::failed("Failed to query the database"),
+                Check::failed("Unknown, as the database is unavailable"),
+            )
+        }
+    };
+
+    let ready = database.ok && migrations.ok;
+    let readiness = Readiness {
+        status: if ready { "ok" } else { "unavailable" },
+        database,
+        migrations,
+        google_certs: gsi_client.cache_status(),
+    };
+
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+/// Run a trivial query, returning whether any migrations are pending
+fn check_database(pool: &Pool) -> anyhow::Result<bool> {
+    let conn = pool.get()?;
+    diesel::sql_query("SELECT 1")
+        .execute(&conn)
+        .context("Failed to run a trivial query")?;
+    db::migrations::any_pending(&conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn test_healthz() {
+        let mut app =
+            test::init_service(App::new().route("/healthz", web::get().to(healthz))).await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(body, serde_json::json!({ "status": "ok" }));
+    }
+}
@@ -0,0 +1,45 @@
+//! Log output, either human-readable or as one JSON object per line. Which
+//! lines are logged is controlled by the `RUST_LOG` environment variable in
+//! either case.
+
+use crate::request_id::RequestId;
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, Utc};
+use log::Record;
+use pretty_env_logger::env_logger::{fmt::Formatter, Builder};
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Config parameters pertaining to logging
+#[derive(Clone, Deserialize)]
+pub struct LogSettings {
+    pub format: String,
+}
+
+/// Install the logger in the configured format
+pub fn init(settings: &LogSettings) -> Result<()> {
+    match settings.format.as_str() {
+        "pretty" => pretty_env_logger::try_init()?,
+        "json" => Builder::from_default_env().format(write_json).try_init()?,
+        format => bail!("log.format must be pretty or json, not '{}'", format),
+    }
+
+    Ok(())
+}
+
+/// Write a record as a single line of JSON, including the ID of the request
+/// being handled, if any
+fn write_json(f: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut line = serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+
+    if let Some(request_id) = RequestId::current() {
+        line["request_id"] = serde_json::Value::from(request_id.0);
+    }
+
+    writeln!(f, "{}", line)
+}
@@ -0,0 +1,230 @@
+//! Email sent by the server, such as invites addressed to an email. Nothing is
+//! sent unless a transport is configured: either `log`, to see what would be
+//! sent, or `http`, which posts each message as JSON to a relay that delivers
+//! it, e.g. a small function in front of the email provider's API.
+
+use crate::telemetry;
+use anyhow::{bail, Context, Result};
+use hyper::client::{Client as HyperClient, HttpConnector};
+use hyper::{header, Body, Request, Uri};
+use hyper_rustls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{field, Instrument};
+
+/// Config parameters pertaining to email
+#[derive(Clone, Deserialize)]
+pub struct MailSettings {
+    pub transport: String,
+    pub from: String,
+    pub url: String,
+    pub token: String,
+    pub timeout_ms: u64,
+}
+
+/// A plain-text email to one recipient
+#[derive(Serialize)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+/// Sends email the way the settings say to. Cloning is cheap, and clones share
+/// the same HTTP client.
+#[derive(Clone)]
+pub enum Mailer {
+    Off,
+    Log { from: String },
+    Http(Box<HttpTransport>),
+}
+
+#[derive(Clone)]
+pub struct HttpTransport {
+    client: HyperClient<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    token: String,
+    from: String,
+    timeout: Duration,
+}
+
+/// The JSON body posted to the relay for each message
+#[derive(Serialize)]
+struct RelayRequest<'a> {
+    from: &'a str,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+impl Mailer {
+    pub fn new(settings: &MailSettings) -> Result<Mailer> {
+        match settings.transport.as_str() {
+            "off" => Ok(Mailer::Off),
+            "log" => Ok(Mailer::Log {
+                from: settings.from.clone(),
+            }),
+            "http" => {
+                let url = settings
+                    .url
+                    .parse::<Uri>()
+                    .with_context(|| format!("mail.url is not a valid URL: '{}'", settings.url))?;
+                if !matches!(url.scheme_str(), Some("http") | Some("https")) {
+                    bail!(
+                        "mail.url must be an http or https URL, not '{}'",
+                        settings.url
+                    );
+                }
+                if settings.from.is_empty() {
+                    bail!("mail.from must be set to send email");
+                }
+                if settings.timeout_ms == 0 {
+                    bail!("mail.timeout_ms must be greater than zero");
+                }
+
+                Ok(Mailer::Http(Box::new(HttpTransport {
+                    client: HyperClient::builder().build(HttpsConnector::new()),
+                    url,
+                    token: settings.token.clone(),
+                    from: settings.from.clone(),
+                    timeout: Duration::from_millis(settings.timeout_ms),
+                })))
+            }
+            other => bail!("mail.transport must be off, log or http, not '{}'", other),
+        }
+    }
+
+    /// Send the message, returning whether it was handed over for delivery.
+    /// Nothing is delivered with the `off` or `log` transports.
+    pub async fn send(&self, message: &Message) -> Result<bool> {
+        match self {
+            Mailer::Off => Ok(false),
+            Mailer::Log { from } => {
+                log::info!(
+                    "Not sending email from {} to {}: {}\n{}",
+                    from,
+                    message.to,
+                    message.subject,
+                    message.text
+                );
+                Ok(false)
+            }
+            Mailer::Http(transport) => transport.send(message).await.map(|_| true),
+        }
+    }
+}
+
+impl HttpTransport {
+    async fn send(&self, message: &Message) -> Result<()> {
+        let span = tracing::info_span!(
+            "Send email",
+            otel.name = "POST mail relay",
+            otel.kind = "client",
+            http.method = "POST",
+            http.status_code = field::Empty,
+        );
+        let response = async {
+            let mut request = self.request(message)?;
+            telemetry::inject_context(request.headers_mut());
+            let response = actix_rt::time::timeout(self.timeout, self.client.request(request))
+                .await
+                .context("Timed out sending email")??;
+            tracing::Span::current().record("http.status_code", &response.status().as_u16());
+            Ok::<_, anyhow::Error>(response)
+        }
+        .instrument(span)
+        .await?;
+
+        if !response.status().is_success() {
+            bail!("Mail relay responded with {}", response.status());
+        }
+        Ok(())
+    }
+
+    /// The request asking the relay to deliver the message
+    fn request(&self, message: &Message) -> Result<Request<Body>> {
+        let body = serde_json::to_vec(&RelayRequest {
+            from: &self.from,
+            message,
+        })?;
+
+        let mut request = Request::post(self.url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))?;
+        if !self.token.is_empty() {
+            request.headers_mut().insert(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.token).parse()?,
+            );
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(transport: &str, url: &str) -> MailSettings {
+        MailSettings {
+            transport: String::from(transport),
+            from: String::from("invites@example.com"),
+            url: String::from(url),
+            token: String::from("hunter2"),
+            timeout_ms: 5000,
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            to: String::from("someone@example.com"),
+            subject: String::from("Hello"),
+            text: String::from("Hi there"),
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(matches!(Mailer::new(&settings("off", "")), Ok(Mailer::Off)));
+        assert!(matches!(
+            Mailer::new(&settings("log", "")),
+            Ok(Mailer::Log { .. })
+        ));
+        assert!(Mailer::new(&settings("http", "https://relay.example.com/send")).is_ok());
+        assert!(Mailer::new(&settings("http", "")).is_err());
+        assert!(Mailer::new(&settings("http", "relay.example.com")).is_err());
+        assert!(Mailer::new(&settings("smtp", "")).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_off_and_log_send_nothing() {
+        for transport in &["off", "log"] {
+            let mailer = Mailer::new(&settings(transport, "")).unwrap();
+            assert!(!mailer.send(&message()).await.unwrap());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_http_request() {
+        let transport = match Mailer::new(&settings("http", "https://relay.example.com/send")) {
+            Ok(Mailer::Http(transport)) => transport,
+            _ => panic!("Expected the http transport"),
+        };
+
+        let request = transport.request(&message()).unwrap();
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.uri(), "https://relay.example.com/send");
+        assert_eq!(request.headers()["Authorization"], "Bearer hunter2");
+        assert_eq!(request.headers()["Content-Type"], "application/json");
+
+        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "from": "invites@example.com",
+                "to": "someone@example.com",
+                "subject": "Hello",
+                "text": "Hi there",
+            })
+        );
+    }
+}
@@ -1,32 +1,60 @@
 mod app;
 mod auth;
+mod cli;
 mod db;
 mod googlesignin;
 mod graphql;
+mod health;
+mod logging;
+mod mail;
+mod metrics;
+mod rate_limit;
+mod request_id;
 mod settings;
+mod shutdown;
+mod telemetry;
+mod tls;
 
 #[macro_use]
 extern crate diesel;
 
 use actix_files::Files;
+use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_web::{middleware, web, App, HttpServer};
 use anyhow::Result;
 use settings::Settings;
 use std::{
-    env,
+    env, io,
     net::{IpAddr, SocketAddr},
     path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
 use structopt::StructOpt;
 
 #[cfg(feature = "autoreload")]
 use listenfd::ListenFd;
 
+/// actix's default access log format, followed by the request ID
+const ACCESS_LOG_FORMAT: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{X-Request-Id}i"#;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "stacks_exchange")]
 struct Opt {
     #[structopt(parse(from_os_str), short, long)]
     conf: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Start the HTTP server. This is the default if no subcommand is given
+    Serve,
+    #[structopt(flatten)]
+    Admin(cli::Command),
 }
 
 #[actix_rt::main]
@@ -34,8 +62,17 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     let opt = Opt::from_args();
     let settings = Settings::init(opt.conf)?;
-    pretty_env_logger::init();
+    logging::init(&settings.log)?;
 
+    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| settings.db.connection_url());
+    match opt.command {
+        Some(Command::Serve) | None => serve(settings, &database_url).await,
+        Some(Command::Admin(command)) => cli::run(command, &settings, &database_url).await,
+    }
+}
+
+/// Start the HTTP server, and run it until it is stopped
+async fn serve(settings: Settings, database_url: &str) -> Result<()> {
     // validate server config values before doing anything else
     let addr = SocketAddr::from((
         settings.server.listen_addr.parse::<IpAddr>()?,
@@ -44,25 +81,93 @@ async fn main() -> Result<()> {
             .map(|s| s.parse::<u16>().unwrap())
             .unwrap_or(settings.server.listen_port),
     ));
-    let pool = db::make_pool(&env::var("DATABASE_URL").unwrap_or(settings.db.to_string()))?;
+    let _telemetry = telemetry::init(&settings.telemetry)?;
+    let pool = db::make_pool(&settings.db, database_url)?;
+
+    let conn = pool.get()?;
+    if settings.db.run_migrations_on_startup {
+        db::migrations::run_pending(&conn, &mut io::stdout())?;
+    } else if db::migrations::any_pending(&conn)? {
+        log::warn!("Database has pending migrations; run `migrate up` to apply them");
+    }
+    drop(conn);
+
     let server_name = settings.server.name.clone();
+    let shutdown_timeout = settings.server.shutdown_timeout_sec;
+    let shutdown_pool = pool.clone();
+    let session_key = auth::session_key(&settings.server)?;
+    let gsi_client = Arc::new(auth::google_sign_in_client(&settings.server));
+    let metrics = metrics::Metrics::new(&settings.metrics, pool.clone(), gsi_client.clone())?;
+    let rate_limit = rate_limit::RateLimit::new(&settings.rate_limit);
+    let persisted_queries =
+        graphql::PersistedQueries::new(&settings.persisted_queries, pool.clone())?;
+    let mailer = mail::Mailer::new(&settings.mail)?;
+
+    let tls_config = if tls::enabled(&settings.server) {
+        Some(tls::server_config(&settings.server)?)
+    } else {
+        None
+    };
+    let https = tls_config.is_some();
+    let redirect_addr = match settings.server.redirect_port {
+        port if https && port != 0 => Some(SocketAddr::new(addr.ip(), port)),
+        _ => None,
+    };
 
     let mut server = HttpServer::new(move || {
         let app = App::new()
-            .data(graphql::make_schema(settings.clone(), pool.clone()))
+            .data(graphql::make_schema(
+                settings.clone(),
+                pool.clone(),
+                metrics.clone(),
+                persisted_queries.clone(),
+                mailer.clone(),
+            ))
             .data(settings.clone())
+            .data(pool.clone())
+            .data(metrics.clone())
+            .app_data(web::Data::from(gsi_client.clone()))
+            .wrap(middleware::Condition::new(
+                https,
+                tls::RedirectToHttps::new(&settings.server.name, addr.port()),
+            ))
+            .wrap(IdentityService::new(
+                CookieIdentityPolicy::new(&session_key)
+                    .name("session")
+                    .secure(https),
+            ))
             .wrap(middleware::Compress::default())
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Logger::new(ACCESS_LOG_FORMAT))
+            .wrap(metrics.http())
+            .wrap(telemetry::RequestTracing)
+            .wrap(request_id::RequestIds)
             .service(
                 web::resource("/oauth")
                     .name("oauth")
+                    .wrap(rate_limit.clone())
                     .route(web::post().to(auth::oauth_handler)),
             )
             .service(
                 web::resource("/graphql")
                     .name("graphql")
+                    .wrap(rate_limit.clone())
                     .route(web::post().to(app::graphql))
                     .route(web::get().to(app::graphql)),
+            )
+            .service(
+                web::resource("/healthz")
+                    .name("healthz")
+                    .route(web::get().to(health::healthz)),
+            )
+            .service(
+                web::resource("/readyz")
+                    .name("readyz")
+                    .route(web::get().to(health::readyz)),
+            )
+            .service(
+                web::resource("/metrics")
+                    .name("metrics")
+                    .route(web::get().to(metrics::metrics)),
             );
 
         let app = if cfg!(feature = "graphiql") {
@@ -74,22 +179,29 @@ async fn main() -> Result<()> {
 
         app.service(Files::new("/", "./static").index_file("index.html"))
     })
-    .server_hostname(server_name);
+    .server_hostname(server_name)
+    .shutdown_timeout(shutdown_timeout)
+    .disable_signals();
 
     #[cfg(feature = "autoreload")]
-    {
-        let mut listenfd = ListenFd::from_env();
-        server = if let Some(l) = listenfd.take_tcp_listener(0)? {
-            server.listen(l)?
-        } else {
-            server.bind(addr)?
-        }
-    }
-
+    let listener = ListenFd::from_env().take_tcp_listener(0)?;
     #[cfg(not(feature = "autoreload"))]
-    {
-        server = server.bind(addr)?;
+    let listener: Option<std::net::TcpListener> = None;
+
+    server = match (listener, tls_config) {
+        (Some(l), Some(config)) => server.listen_rustls(l, config)?,
+        (Some(l), None) => server.listen(l)?,
+        (None, Some(config)) => server.bind_rustls(addr, config)?,
+        (None, None) => server.bind(addr)?,
+    };
+    if let Some(redirect_addr) = redirect_addr {
+        server = server.bind(redirect_addr)?;
     }
 
-    Ok(server.run().await?)
+    shutdown::run(
+        server.run(),
+        shutdown_pool,
+        Duration::from_secs(shutdown_timeout),
+    )
+    .await
 }
@@ -0,0 +1,559 @@
+//! Prometheus metrics, exposed at `/metrics` to scrapers holding the bearer
+//! token. HTTP requests are counted by route, GraphQL requests by operation
+//! name, and the state of the database pool and Google certificate cache is
+//! sampled whenever metrics are scraped.
+
+use crate::db::Pool;
+use crate::googlesignin::GoogleSignInClient;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::header, web, Error, HttpRequest, HttpResponse};
+use anyhow::Result;
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory};
+use async_graphql::parser::types::{DocumentOperations, ExecutableDocument};
+use async_graphql::{Request, ServerError, ServerResult};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Config parameters pertaining to metrics
+#[derive(Clone, Deserialize)]
+pub struct MetricsSettings {
+    pub token: String,
+    pub max_operations: usize,
+}
+
+/// Every metric reported by the server. Cloning is cheap, and clones record to
+/// the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    token: String,
+    max_operations: usize,
+    operations: Arc<Mutex<HashSet<String>>>,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    graphql_requests: IntCounterVec,
+    graphql_request_duration: HistogramVec,
+    graphql_errors: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(
+        settings: &MetricsSettings,
+        pool: Pool,
+        gsi_client: Arc<GoogleSignInClient>,
+    ) -> Result<Metrics> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route"],
+        )?;
+        let graphql_requests = IntCounterVec::new(
+            Opts::new("graphql_requests_total", "GraphQL requests executed"),
+            &["operation"],
+        )?;
+        let graphql_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_request_duration_seconds",
+                "Time taken to parse, validate and execute GraphQL requests",
+            ),
+            &["operation"],
+        )?;
+        let graphql_errors = IntCounterVec::new(
+            Opts::new(
+                "graphql_errors_total",
+                "GraphQL requests which responded with at least one error",
+            ),
+            &["operation"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(graphql_requests.clone()))?;
+        registry.register(Box::new(graphql_request_duration.clone()))?;
+        registry.register(Box::new(graphql_errors.clone()))?;
+        registry.register(Box::new(PoolCollector::new(pool)?))?;
+        registry.register(Box::new(CertCacheCollector::new(gsi_client)?))?;
+
+        Ok(Metrics {
+            registry,
+            token: settings.token.clone(),
+            max_operations: settings.max_operations,
+            operations: Arc::new(Mutex::new(HashSet::new())),
+            http_requests,
+            http_request_duration,
+            graphql_requests,
+            graphql_request_duration,
+            graphql_errors,
+        })
+    }
+
+    /// Middleware which records every HTTP request
+    pub fn http(&self) -> HttpMetrics {
+        HttpMetrics(self.clone())
+    }
+
+    /// Schema extension which records every GraphQL request
+    pub fn graphql(&self) -> GraphQLMetrics {
+        GraphQLMetrics(self.clone())
+    }
+
+    /// The label for a GraphQL operation. Operation names are chosen by
+    /// clients, so once `max_operations` names have been seen any others are
+    /// labelled `other`, keeping the number of distinct labels bounded.
+    fn operation_label(&self, operation: Option<&str>) -> String {
+        let name = match operation {
+            Some(name) => name,
+            None => return "anonymous".to_string(),
+        };
+        let mut operations = self.operations.lock().unwrap();
+        if operations.contains(name) {
+            return name.to_string();
+        }
+        if operations.len() < self.max_operations {
+            operations.insert(name.to_string());
+            return name.to_string();
+        }
+        "other".to_string()
+    }
+
+    /// Whether a request to `/metrics` carries the configured bearer token
+    fn authorized(&self, req: &HttpRequest) -> bool {
+        let presented = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match presented {
+            // Compare every byte, so that the time taken doesn't reveal how
+            // much of the token was right
+            Some(presented) if presented.len() == self.token.len() => {
+                presented
+                    .bytes()
+                    .zip(self.token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Encode every metric in the Prometheus text format, returning the
+    /// content type along with the encoded metrics
+    fn render(&self) -> Result<(String, Vec<u8>)> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok((encoder.format_type().to_string(), buffer))
+    }
+}
+
+/// Handler which reports every metric in the Prometheus text format. Metrics
+/// are only served when `metrics.token` is set, and only to requests which
+/// present it as a bearer token.
+pub async fn metrics(req: HttpRequest, metrics: web::Data<Metrics>) -> HttpResponse {
+    if metrics.token.is_empty() {
+        return HttpResponse::NotFound().finish();
+    }
+    if !metrics.authorized(&req) {
+        return HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .finish();
+    }
+
+    match metrics.render() {
+        Ok((content_type, body)) => HttpResponse::Ok().content_type(content_type).body(body),
+        Err(e) => {
+            log::error!("Failed to render metrics: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub struct HttpMetrics(Metrics);
+
+impl<S, B> Transform<S> for HttpMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HttpMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HttpMetricsMiddleware {
+            service,
+            metrics: self.0.clone(),
+        })
+    }
+}
+
+pub struct HttpMetricsMiddleware<S> {
+    service: S,
+    metrics: Metrics,
+}
+
+impl<S, B> Service for HttpMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        // Label by the pattern of the matched route rather than the path, so
+        // that the number of distinct labels stays small
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let metrics = self.metrics.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+
+            metrics
+                .http_requests
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            metrics
+                .http_request_duration
+                .with_label_values(&[&method, &route])
+                .observe(start.elapsed().as_secs_f64());
+
+            res
+        })
+    }
+}
+
+pub struct GraphQLMetrics(Metrics);
+
+impl ExtensionFactory for GraphQLMetrics {
+    fn create(&self) -> Box<dyn Extension> {
+        Box::new(GraphQLMetricsExtension {
+            metrics: self.0.clone(),
+            start: Instant::now(),
+            operation: None,
+            failed: false,
+        })
+    }
+}
+
+/// Records a GraphQL request when it is dropped, which happens however far
+/// the request got, including when it fails to parse or validate
+struct GraphQLMetricsExtension {
+    metrics: Metrics,
+    start: Instant,
+    operation: Option<String>,
+    failed: bool,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for GraphQLMetricsExtension {
+    async fn prepare_request(
+        &mut self,
+        _ctx: &ExtensionContext<'_>,
+        request: Request,
+    ) -> ServerResult<Request> {
+        self.start = Instant::now();
+        self.operation = request.operation_name.clone();
+        Ok(request)
+    }
+
+    fn parse_end(&mut self, _ctx: &ExtensionContext<'_>, document: &ExecutableDocument) {
+        // Operation names are chosen by clients, so only use names which are
+        // actually defined by the document
+        self.operation = match &document.operations {
+            DocumentOperations::Single(_) => None,
+            DocumentOperations::Multiple(operations) => match &self.operation {
+                Some(name) => operations
+                    .keys()
+                    .find(|key| key.as_str() == name.as_str())
+                    .map(|key| key.to_string()),
+                None if operations.len() == 1 => {
+                    operations.keys().next().map(|key| key.to_string())
+                }
+                None => None,
+            },
+        };
+    }
+
+    fn error(&mut self, _ctx: &ExtensionContext<'_>, _err: &ServerError) {
+        self.failed = true;
+    }
+}
+
+impl Drop for GraphQLMetricsExtension {
+    fn drop(&mut self) {
+        let operation = self.metrics.operation_label(self.operation.as_deref());
+        let operation = operation.as_str();
+
+        self.metrics
+            .graphql_requests
+            .with_label_values(&[operation])
+            .inc();
+        self.metrics
+            .graphql_request_duration
+            .with_label_values(&[operation])
+            .observe(self.start.elapsed().as_secs_f64());
+        if self.failed {
+            self.metrics
+                .graphql_errors
+                .with_label_values(&[operation])
+                .inc();
+        }
+    }
+}
+
+/// Samples the state of the database pool whenever metrics are gathered
+struct PoolCollector {
+    pool: Pool,
+    connections: IntGauge,
+    idle_connections: IntGauge,
+    max_size: IntGauge,
+}
+
+impl PoolCollector {
+    fn new(pool: Pool) -> Result<PoolCollector> {
+        Ok(PoolCollector {
+            pool,
+            connections: IntGauge::new(
+                "db_pool_connections",
+                "Connections to the database, whether idle or in use",
+            )?,
+            idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Connections to the database which are not in use",
+            )?,
+            max_size: IntGauge::new(
+                "db_pool_max_size",
+                "Most connections the pool will open to the database",
+            )?,
+        })
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        [&self.connections, &self.idle_connections, &self.max_size]
+            .iter()
+            .flat_map(|gauge| gauge.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let state = self.pool.state();
+        self.connections.set(i64::from(state.connections));
+        self.idle_connections.set(i64::from(state.idle_connections));
+        self.max_size.set(i64::from(self.pool.max_size()));
+
+        [&self.connections, &self.idle_connections, &self.max_size]
+            .iter()
+            .flat_map(|gauge| gauge.collect())
+            .collect()
+    }
+}
+
+/// Reports the totals kept by the Google certificate cache whenever metrics
+/// are gathered
+struct CertCacheCollector {
+    gsi_client: Arc<GoogleSignInClient>,
+    hits: IntCounter,
+    refreshes: IntCounter,
+    refresh_errors: IntCounter,
+}
+
+impl CertCacheCollector {
+    fn new(gsi_client: Arc<GoogleSignInClient>) -> Result<CertCacheCollector> {
+        Ok(CertCacheCollector {
+            gsi_client,
+            hits: IntCounter::new(
+                "google_cert_cache_hits_total",
+                "Sign-ins verified using already cached Google certificates",
+            )?,
+            refreshes: IntCounter::new(
+                "google_cert_cache_refreshes_total",
+                "Requests made to Google for its certificates",
+            )?,
+            refresh_errors: IntCounter::new(
+                "google_cert_cache_refresh_errors_total",
+                "Requests made to Google for its certificates which failed",
+            )?,
+        })
+    }
+}
+
+impl Collector for CertCacheCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        [&self.hits, &self.refreshes, &self.refresh_errors]
+            .iter()
+            .flat_map(|counter| counter.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let stats = self.gsi_client.cache_stats();
+
+        // The cache keeps the totals, so report them through fresh counters
+        // rather than trying to keep ours in step
+        [
+            (&self.hits, stats.hits),
+            (&self.refreshes, stats.refreshes),
+            (&self.refresh_errors, stats.refresh_errors),
+        ]
+        .iter()
+        .flat_map(|(counter, total)| {
+            let desc = &counter.desc()[0];
+            let counter = IntCounter::new(desc.fq_name.clone(), desc.help.clone())
+                .expect("Counter options were already validated");
+            counter.inc_by(*total);
+            counter.collect()
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::traced::TracedConnection;
+    use crate::graphql;
+    use diesel::r2d2::ConnectionManager;
+
+    use actix_web::{http::StatusCode, test};
+
+    fn metrics(token: &str, max_operations: usize) -> Metrics {
+        // The pool is never used, so needn't be able to connect
+        let pool = Pool::builder().build_unchecked(ConnectionManager::<TracedConnection>::new(""));
+        let settings = MetricsSettings {
+            token: token.to_string(),
+            max_operations,
+        };
+        Metrics::new(&settings, pool, Arc::new(GoogleSignInClient::new())).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_graphql_metrics() {
+        let metrics = metrics("", 100);
+        let schema = graphql::schema_builder()
+            .extension(metrics.graphql())
+            .finish();
+
+        schema
+            .execute("query Introspect { __schema { queryType { name } } }")
+            .await;
+        schema.execute("query Broken {").await;
+        schema
+            .execute(
+                Request::new("query A { __typename } query B { __typename }").operation_name("C"),
+            )
+            .await;
+
+        let requests = |operation| {
+            metrics
+                .graphql_requests
+                .with_label_values(&[operation])
+                .get()
+        };
+        let errors = |operation| metrics.graphql_errors.with_label_values(&[operation]).get();
+        assert_eq!(requests("Introspect"), 1);
+        assert_eq!(errors("Introspect"), 0);
+        assert_eq!(requests("anonymous"), 2);
+        assert_eq!(errors("anonymous"), 2);
+        assert_eq!(requests("C"), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_operation_names_are_capped() {
+        let metrics = metrics("", 1);
+        let schema = graphql::schema_builder()
+            .extension(metrics.graphql())
+            .finish();
+
+        schema.execute("query First { __typename }").await;
+        schema.execute("query Second { __typename }").await;
+        schema.execute("query First { __typename }").await;
+        schema.execute("query Third { __typename }").await;
+
+        let requests = |operation| {
+            metrics
+                .graphql_requests
+                .with_label_values(&[operation])
+                .get()
+        };
+        assert_eq!(requests("First"), 2);
+        assert_eq!(requests("Second"), 0);
+        assert_eq!(requests("other"), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_require_token() {
+        let get = |metrics: Metrics, authorization: Option<&str>| {
+            let req = match authorization {
+                Some(value) => test::TestRequest::get().header(header::AUTHORIZATION, value),
+                None => test::TestRequest::get(),
+            };
+            super::metrics(req.to_http_request(), web::Data::new(metrics))
+        };
+
+        assert_eq!(
+            get(metrics("", 100), None).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get(metrics("", 100), Some("Bearer ")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get(metrics("secret", 100), None).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get(metrics("secret", 100), Some("Bearer secreT"))
+                .await
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get(metrics("secret", 100), Some("Bearer secret"))
+                .await
+                .status(),
+            StatusCode::OK
+        );
+    }
+}
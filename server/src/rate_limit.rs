@@ -0,0 +1,438 @@
+//! Rate limiting for the endpoints which do real work (`/graphql` and
+//! `/oauth`), per client IP address and per signed-in person. Each client may
+//! make a burst of up to a minute's worth of requests, after which requests are
+//! allowed at the configured rate, and anything faster is rejected with 429
+//! Too Many Requests.
+
+use actix_identity::RequestIdentity;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::header, Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// Config parameters pertaining to rate limiting
+#[derive(Clone, Deserialize)]
+pub struct RateLimitSettings {
+    pub per_ip_per_minute: u32,
+    pub per_person_per_minute: u32,
+    pub trusted_proxies: usize,
+}
+
+/// A token bucket for each client, which holds up to a minute's worth of
+/// requests and refills continuously
+struct Limiter {
+    per_minute: u32,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    last_pruned: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limiter {
+    fn new(per_minute: u32) -> Option<Limiter> {
+        if per_minute == 0 {
+            return None;
+        }
+
+        Some(Limiter {
+            per_minute,
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        })
+    }
+
+    /// Lock the buckets, so that requests can be checked against them and
+    /// then counted without other requests spending the same tokens
+    fn lock(&self, now: Instant) -> LockedLimiter<'_> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let capacity = f64::from(self.per_minute);
+
+        // Forget clients whose buckets have refilled, so that they don't
+        // accumulate forever
+        if now.saturating_duration_since(buckets.last_pruned) > Duration::from_secs(60) {
+            let per_sec = capacity / 60.0;
+            buckets.by_key.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated);
+                bucket.tokens + elapsed.as_secs_f64() * per_sec < capacity
+            });
+            buckets.last_pruned = now;
+        }
+
+        LockedLimiter {
+            capacity,
+            buckets,
+            now,
+        }
+    }
+}
+
+/// A limiter's buckets, locked at a moment in time
+struct LockedLimiter<'a> {
+    capacity: f64,
+    buckets: MutexGuard<'a, Buckets>,
+    now: Instant,
+}
+
+impl LockedLimiter<'_> {
+    fn per_sec(&self) -> f64 {
+        self.capacity / 60.0
+    }
+
+    /// The client's bucket, refilled for the time since it was last used
+    fn bucket(&mut self, key: &str) -> &mut Bucket {
+        let (capacity, per_sec, now) = (self.capacity, self.per_sec(), self.now);
+        let bucket = self
+            .buckets
+            .by_key
+            .entry(key.to_string())
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * per_sec).min(capacity);
+        bucket.updated = now;
+        bucket
+    }
+
+    /// Whether the client has a token to spend, or how long until they will
+    fn check(&mut self, key: &str) -> Result<(), Duration> {
+        let per_sec = self.per_sec();
+        let tokens = self.bucket(key).tokens;
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / per_sec))
+        }
+    }
+
+    /// Spend one of the client's tokens, which `check` must have found
+    fn take(&mut self, key: &str) {
+        self.bucket(key).tokens -= 1.0;
+    }
+}
+
+struct Limits {
+    per_ip: Option<Limiter>,
+    per_person: Option<Limiter>,
+    trusted_proxies: usize,
+}
+
+/// Middleware which applies the configured rate limits. Clones share the same
+/// limits, so the same instance should wrap every rate-limited resource in
+/// every worker. Must be wrapped by the `IdentityService`.
+#[derive(Clone)]
+pub struct RateLimit(Arc<Limits>);
+
+impl RateLimit {
+    pub fn new(settings: &RateLimitSettings) -> RateLimit {
+        RateLimit(Arc::new(Limits {
+            per_ip: Limiter::new(settings.per_ip_per_minute),
+            per_person: Limiter::new(settings.per_person_per_minute),
+            trusted_proxies: settings.trusted_proxies,
+        }))
+    }
+}
+
+impl Limits {
+    /// Count the request against the client's IP address and the signed-in
+    /// person, unless either has no requests left, in which case neither is
+    /// charged
+    fn check(&self, req: &ServiceRequest) -> Result<(), Duration> {
+        let now = Instant::now();
+        let per_ip = self
+            .per_ip
+            .as_ref()
+            .and_then(|limiter| Some((limiter.lock(now), self.client_ip(req)?)));
+        let per_person = self
+            .per_person
+            .as_ref()
+            .and_then(|limiter| Some((limiter.lock(now), req.get_identity()?)));
+        let mut limits = per_ip.into_iter().chain(per_person).collect::<Vec<_>>();
+
+        for (limiter, key) in &mut limits {
+            limiter.check(key)?;
+        }
+        for (limiter, key) in &mut limits {
+            limiter.take(key);
+        }
+
+        Ok(())
+    }
+
+    /// The client's IP address. Behind `trusted_proxies` proxies, each of which
+    /// appends the address it received the request from to `X-Forwarded-For`,
+    /// that is the entry added by the outermost one, so that clients can't
+    /// choose their own address by sending the header themselves
+    fn client_ip(&self, req: &ServiceRequest) -> Option<String> {
+        let forwarded = match self.trusted_proxies {
+            0 => None,
+            hops => {
+                let entries = req
+                    .headers()
+                    .get_all(X_FORWARDED_FOR)
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .map(|entry| entry.trim().to_string())
+                    .collect::<Vec<_>>();
+                entries
+                    .len()
+                    .checked_sub(hops)
+                    .map_or_else(|| entries.first(), |i| entries.get(i))
+                    .cloned()
+            }
+        };
+        let addr = match forwarded {
+            Some(addr) => addr,
+            None => req.peer_addr()?.to_string(),
+        };
+
+        // Clients' ports vary from one connection to the next
+        match addr.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr.ip().to_string()),
+            Err(_) => Some(addr),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service,
+            limits: self.0.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limits: Arc<Limits>,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let retry_after = match self.limits.check(&req) {
+            Ok(()) => return Either::Left(self.service.call(req)),
+            Err(retry_after) => retry_after,
+        };
+
+        let res = HttpResponse::TooManyRequests()
+            .header(
+                header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().to_string(),
+            )
+            .body("Too many requests")
+            .into_body();
+        Either::Right(ok(req.into_response(res)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_identity::{CookieIdentityPolicy, Identity, IdentityService};
+    use actix_web::{test, web, App};
+
+    #[test]
+    fn test_limiter() {
+        let limiter = Limiter::new(60).unwrap();
+        let start = Instant::now();
+        let spend = |key: &str, now: Instant| {
+            let mut locked = limiter.lock(now);
+            locked.check(key)?;
+            locked.take(key);
+            Ok(())
+        };
+
+        // A burst of a minute's worth of requests is allowed, then no more
+        for _ in 0..60 {
+            assert!(spend("a", start).is_ok());
+        }
+        assert_eq!(spend("a", start), Err(Duration::from_secs(1)));
+
+        // Other clients are unaffected
+        assert!(spend("b", start).is_ok());
+
+        // Requests are allowed again as the bucket refills
+        let later = start + Duration::from_millis(1500);
+        assert!(spend("a", later).is_ok());
+        assert!(spend("a", later).is_err());
+
+        // Checking alone spends nothing
+        assert!(limiter.lock(start).check("c").is_ok());
+        assert_eq!(limiter.lock(start).bucket("c").tokens, 60.0);
+
+        // Full buckets are eventually forgotten
+        let much_later = start + Duration::from_secs(300);
+        assert!(spend("d", much_later).is_ok());
+        assert_eq!(
+            limiter
+                .buckets
+                .lock()
+                .unwrap()
+                .by_key
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["d"]
+        );
+
+        assert!(Limiter::new(0).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_rate_limit() {
+        let settings = RateLimitSettings {
+            per_ip_per_minute: 2,
+            per_person_per_minute: 0,
+            trusted_proxies: 0,
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new(&settings))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = |ip: &str| {
+            test::TestRequest::get()
+                .uri("/")
+                .peer_addr(ip.parse().unwrap())
+                .header(X_FORWARDED_FOR, "192.0.2.1")
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let resp = test::call_service(&mut app, request("198.51.100.1:1234")).await;
+            assert_eq!(resp.status(), 200);
+        }
+        let resp = test::call_service(&mut app, request("198.51.100.1:5678")).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+        let resp = test::call_service(&mut app, request("198.51.100.2:1234")).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_spoofed_forwarded_for() {
+        let settings = RateLimitSettings {
+            per_ip_per_minute: 1,
+            per_person_per_minute: 0,
+            trusted_proxies: 1,
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new(&settings))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        // The client makes up a different left-most entry for each request,
+        // but the proxy appends their real address
+        for (i, expected) in [200, 429, 429].iter().enumerate() {
+            let req = test::TestRequest::get()
+                .uri("/")
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .header(X_FORWARDED_FOR, format!("192.0.2.{}, 198.51.100.1", i))
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *expected);
+        }
+
+        // Another client behind the same proxy is unaffected
+        let req = test::TestRequest::get()
+            .uri("/")
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .header(X_FORWARDED_FOR, "198.51.100.2")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_rejection_spends_nothing() {
+        let settings = RateLimitSettings {
+            per_ip_per_minute: 3,
+            per_person_per_minute: 1,
+            trusted_proxies: 0,
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new(&settings))
+                .wrap(IdentityService::new(
+                    CookieIdentityPolicy::new(&[0; 32]).secure(false),
+                ))
+                .route(
+                    "/login",
+                    web::get().to(|id: Identity| {
+                        id.remember("alice@example.com".to_string());
+                        HttpResponse::Ok()
+                    }),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .peer_addr("198.51.100.1:1234".parse().unwrap())
+        };
+
+        // Signing in spends one of the IP address's three requests
+        let resp = test::call_service(&mut app, request("/login").to_request()).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        // The person's first request spends another, and their second is
+        // rejected without spending the last
+        let resp =
+            test::call_service(&mut app, request("/").cookie(cookie.clone()).to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&mut app, request("/").cookie(cookie).to_request()).await;
+        assert_eq!(resp.status(), 429);
+
+        let resp = test::call_service(&mut app, request("/").to_request()).await;
+        assert_eq!(resp.status(), 200);
+    }
+}
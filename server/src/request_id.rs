@@ -0,0 +1,173 @@
+//! Identifiers for HTTP requests, which are included in every log line written
+//! while handling a request, in the audit log, and in GraphQL errors, so that
+//! they can be correlated with one another and with the logs of any proxies in
+//! front of the server.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+use uuid::Uuid;
+
+/// Longest request ID accepted from the `X-Request-Id` header
+const MAX_LEN: usize = 128;
+
+const HEADER: &str = "x-request-id";
+
+/// Identifier of an HTTP request, taken from its `X-Request-Id` header if it
+/// has a valid one, and otherwise generated
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+impl RequestId {
+    /// Accept an ID provided by a client or proxy only if it is reasonably
+    /// short and cannot be used to forge log lines
+    fn from_header(value: &HeaderValue) -> Option<RequestId> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LEN
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+
+        if valid {
+            Some(RequestId(value.to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// The ID of the request being handled, if any
+    pub fn current() -> Option<RequestId> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+}
+
+/// Middleware which assigns every request an ID, available from the request's
+/// extensions and from `RequestId::current`, and returns it in the
+/// `X-Request-Id` response header
+pub struct RequestIds;
+
+impl<S, B> Transform<S> for RequestIds
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdsMiddleware { service })
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestIdsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(|| RequestId(Uuid::new_v4().to_string()));
+        req.extensions_mut().insert(request_id.clone());
+
+        // Replace the request's header too, so that inner middleware such as
+        // the access log sees the ID actually used
+        let header_value = HeaderValue::from_str(&request_id.0);
+        if let Ok(value) = &header_value {
+            req.headers_mut()
+                .insert(HeaderName::from_static(HEADER), value.clone());
+        }
+
+        let fut = CURRENT.sync_scope(request_id.clone(), || self.service.call(req));
+
+        Box::pin(CURRENT.scope(request_id, async move {
+            let mut res = fut.await?;
+            if let Ok(value) = header_value {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(HEADER), value);
+            }
+            Ok(res)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn echo() -> HttpResponse {
+        HttpResponse::Ok().body(RequestId::current().map(|id| id.0).unwrap_or_default())
+    }
+
+    #[actix_rt::test]
+    async fn test_request_ids() {
+        let mut app =
+            test::init_service(App::new().wrap(RequestIds).route("/", web::get().to(echo))).await;
+
+        // A well-formed ID is passed through
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(HEADER, "abc-123")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.headers().get(HEADER).unwrap(), "abc-123");
+        assert_eq!(test::read_body(resp).await, "abc-123");
+
+        // Anything else is replaced, as is a missing ID
+        let too_long = "a".repeat(MAX_LEN + 1);
+        for header in &[
+            Some("forged log line"),
+            Some(""),
+            Some(too_long.as_str()),
+            None,
+        ] {
+            let mut req = test::TestRequest::get().uri("/");
+            if let Some(header) = header {
+                req = req.header(HEADER, header.to_string());
+            }
+            let resp = test::call_service(&mut app, req.to_request()).await;
+            let id = resp
+                .headers()
+                .get(HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert!(
+                Uuid::parse_str(&id).is_ok(),
+                "Expected a generated ID, not {}",
+                id
+            );
+            assert_eq!(test::read_body(resp).await, id.as_bytes());
+        }
+
+        assert!(RequestId::current().is_none());
+    }
+}
@@ -1,4 +1,10 @@
 use crate::db::DatabaseSettings;
+use crate::graphql::{GraphQLSettings, PersistedQuerySettings};
+use crate::logging::LogSettings;
+use crate::mail::MailSettings;
+use crate::metrics::MetricsSettings;
+use crate::rate_limit::RateLimitSettings;
+use crate::telemetry::TelemetrySettings;
 use anyhow::Result;
 use config::{Config, Environment, File};
 use serde::Deserialize;
@@ -11,6 +17,11 @@ pub struct ServerSettings {
     pub listen_port: u16,
     pub name: String,
     pub google_client_id: String,
+    pub session_key: String,
+    pub shutdown_timeout_sec: u64,
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub redirect_port: u16,
 }
 
 /// Container for all config parameters
@@ -18,6 +29,13 @@ pub struct ServerSettings {
 pub struct Settings {
     pub server: ServerSettings,
     pub db: DatabaseSettings,
+    pub graphql: GraphQLSettings,
+    pub log: LogSettings,
+    pub mail: MailSettings,
+    pub metrics: MetricsSettings,
+    pub persisted_queries: PersistedQuerySettings,
+    pub rate_limit: RateLimitSettings,
+    pub telemetry: TelemetrySettings,
 }
 
 impl Settings {
@@ -35,6 +53,9 @@ impl Settings {
         // apply any conf overrides provided in env (e.g. DEF_addr="...")
         cfg.merge(Environment::with_prefix("DEF").separator("."))?;
 
-        Ok(cfg.try_into()?)
+        let settings: Settings = cfg.try_into()?;
+        settings.db.validate()?;
+
+        Ok(settings)
     }
 }
@@ -0,0 +1,62 @@
+//! Graceful shutdown. On SIGINT or SIGTERM the server stops accepting
+//! connections and waits for in-flight requests (in particular, mutations) to
+//! finish and for their database connections to be returned, up to
+//! `server.shutdown_timeout_sec` in all.
+
+use crate::db::{self, Pool};
+use actix_web::dev::Server;
+use anyhow::Result;
+use futures::future::{self, Either};
+use std::time::{Duration, Instant};
+
+/// Wait for a signal to shut down, returning its name
+#[cfg(unix)]
+async fn signal() -> Result<&'static str> {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let received = future::select(Box::pin(interrupt.recv()), Box::pin(terminate.recv())).await;
+    Ok(match received {
+        Either::Left(_) => "SIGINT",
+        Either::Right(_) => "SIGTERM",
+    })
+}
+
+#[cfg(not(unix))]
+async fn signal() -> Result<&'static str> {
+    actix_rt::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+/// Run the server until it receives a signal to shut down, then shut it down
+/// gracefully. The server must have been built with `disable_signals`, and its
+/// `shutdown_timeout` set to `timeout`.
+pub async fn run(server: Server, pool: Pool, timeout: Duration) -> Result<()> {
+    let signal = match future::select(server.clone(), Box::pin(signal())).await {
+        Either::Left((result, _)) => return Ok(result?),
+        Either::Right((signal, _)) => signal?,
+    };
+
+    let deadline = Instant::now() + timeout;
+    log::info!(
+        "{} received; finishing in-flight requests for up to {}s",
+        signal,
+        timeout.as_secs()
+    );
+
+    // Stops accepting connections, then waits for open ones to close, or for
+    // the timeout to pass, at which point their requests are cancelled
+    server.stop(true).await;
+
+    if db::drain(&pool, deadline).await {
+        log::info!("Shut down cleanly");
+    } else {
+        log::warn!(
+            "Shut down with {} database connection(s) still in use",
+            pool.state().connections - pool.state().idle_connections
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Distributed tracing with OpenTelemetry. When an OTLP endpoint is configured,
+//! spans for each HTTP request, GraphQL field and database call are exported to
+//! it, continuing any trace passed in the W3C `traceparent` header. Requests
+//! the server makes itself pass the trace on in the same header.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::HeaderMap;
+use actix_web::Error;
+use anyhow::Result;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use serde::Deserialize;
+use std::task::{Context, Poll};
+use tracing::{field, Instrument, Level};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt};
+
+/// Config parameters pertaining to tracing
+#[derive(Clone, Deserialize)]
+pub struct TelemetrySettings {
+    pub otlp_endpoint: String,
+    pub service_name: String,
+}
+
+/// Keeps spans being exported until it is dropped, at which point any which
+/// have yet to be exported are flushed
+pub struct Telemetry {
+    // The exporter uses tonic, which needs a newer tokio than actix runs on
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if self.runtime.is_some() {
+            global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Start exporting spans to the configured OTLP endpoint. Does nothing but
+/// accept incoming trace context if no endpoint is configured.
+pub fn init(settings: &TelemetrySettings) -> Result<Telemetry> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    if settings.otlp_endpoint.is_empty() {
+        return Ok(Telemetry { runtime: None });
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("otlp-exporter")
+        .enable_all()
+        .build()?;
+
+    let tracer = {
+        let _guard = runtime.enter();
+        otlp_tracer(settings)?
+    };
+
+    // Only export our own spans and those of the GraphQL executor, rather than
+    // those of every library (including the exporter itself)
+    let targets = Targets::new()
+        .with_target("stacks_exchange", Level::INFO)
+        .with_target("async_graphql", Level::INFO);
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(targets);
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    log::info!("Exporting traces to {}", settings.otlp_endpoint);
+    Ok(Telemetry {
+        runtime: Some(runtime),
+    })
+}
+
+/// Build a tracer which exports spans in batches. Must be called within a
+/// tokio runtime, on which the exporter will run.
+fn otlp_tracer(settings: &TelemetrySettings) -> Result<trace::Tracer> {
+    let resource = Resource::new(vec![KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]);
+
+    Ok(opentelemetry_otlp::new_pipeline()
+        .with_endpoint(settings.otlp_endpoint.as_str())
+        .with_trace_config(trace::config().with_resource(resource))
+        .with_tonic()
+        .install_batch(opentelemetry::runtime::Tokio)?)
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct HyperHeaderInjector<'a>(&'a mut hyper::HeaderMap);
+
+impl<'a> Injector for HyperHeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(key.as_bytes()),
+            hyper::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Add the current span's trace context to the headers of an outgoing
+/// request, so that the server receiving it can continue the trace
+pub fn inject_context(headers: &mut hyper::HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HyperHeaderInjector(headers))
+    });
+}
+
+/// Middleware which runs every HTTP request within a span
+pub struct RequestTracing;
+
+impl<S, B> Transform<S> for RequestTracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestTracingMiddleware { service })
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestTracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let span = tracing::info_span!(
+            "HTTP request",
+            otel.name = %format!("{} {}", req.method(), route),
+            otel.kind = "server",
+            http.method = %req.method(),
+            http.route = %route,
+            http.target = %req.path(),
+            http.status_code = field::Empty,
+        );
+        span.set_parent(parent);
+
+        let fut = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let res = fut.await;
+                let status = match &res {
+                    Ok(res) => res.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                };
+                tracing::Span::current().record("http.status_code", &status.as_u16());
+                res
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+
+    #[test]
+    fn test_inject_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        // The tracer only holds a weak reference to its provider
+        let provider = trace::TracerProvider::builder().build();
+        let tracer = provider.get_tracer("test", None);
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("outgoing");
+            let _entered = span.enter();
+            let mut headers = hyper::HeaderMap::new();
+            inject_context(&mut headers);
+
+            let span_context = span.context().span().span_context().clone();
+            assert_eq!(
+                headers["traceparent"],
+                format!(
+                    "00-{}-{}-01",
+                    span_context.trace_id().to_hex(),
+                    span_context.span_id().to_hex()
+                )
+            );
+        });
+    }
+}
@@ -0,0 +1,223 @@
+//! HTTPS. When a certificate chain and key are configured, the server listens
+//! for HTTPS on `server.listen_port`, reloads the certificate and key on SIGHUP
+//! (e.g. after they are renewed), and optionally redirects plain HTTP on
+//! `server.redirect_port` to HTTPS.
+
+use crate::settings::ServerSettings;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::header, Error, HttpResponse};
+use anyhow::{bail, Context as _, Result};
+use futures::future::{ok, Either, Ready};
+use rustls::{
+    internal::pemfile, sign, sign::CertifiedKey, ClientHello, NoClientAuth, ResolvesServerCert,
+    ServerConfig,
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+/// Whether HTTPS is configured
+pub fn enabled(settings: &ServerSettings) -> bool {
+    !settings.tls_cert.is_empty() || !settings.tls_key.is_empty()
+}
+
+/// Build the TLS config for the server, and start reloading its certificate
+/// whenever the process receives SIGHUP
+pub fn server_config(settings: &ServerSettings) -> Result<ServerConfig> {
+    if settings.tls_cert.is_empty() || settings.tls_key.is_empty() {
+        bail!("server.tls_cert and server.tls_key must be set together");
+    }
+
+    let resolver = Arc::new(CertResolver {
+        cert_path: settings.tls_cert.clone(),
+        key_path: settings.tls_key.clone(),
+        current: RwLock::new(load(&settings.tls_cert, &settings.tls_key)?),
+    });
+
+    #[cfg(unix)]
+    actix_rt::spawn(reload_on_sighup(resolver.clone()));
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    Ok(config)
+}
+
+/// Read a PEM certificate chain, and its PKCS#8 or RSA private key
+fn load(cert_path: &str, key_path: &str) -> Result<CertifiedKey> {
+    let open = |path| -> Result<_> {
+        let file = File::open(path).with_context(|| format!("Failed to open '{}'", path))?;
+        Ok(BufReader::new(file))
+    };
+
+    let certs = match pemfile::certs(&mut open(cert_path)?) {
+        Ok(certs) if !certs.is_empty() => certs,
+        _ => bail!("'{}' contains no PEM certificates", cert_path),
+    };
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?).unwrap_or_default();
+    }
+    let key = match keys.first() {
+        Some(key) => key,
+        None => bail!("'{}' contains no PEM private key", key_path),
+    };
+    let key = match sign::any_supported_type(key) {
+        Ok(key) => key,
+        Err(()) => bail!("'{}' contains an unsupported type of key", key_path),
+    };
+
+    let certified_key = CertifiedKey::new(certs, Arc::new(key));
+    certified_key
+        .cross_check_end_entity_cert(None)
+        .with_context(|| format!("'{}' does not match '{}'", key_path, cert_path))?;
+
+    Ok(certified_key)
+}
+
+/// Provides the most recently loaded certificate for every handshake
+struct CertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<CertifiedKey>,
+}
+
+impl CertResolver {
+    fn reload(&self) -> Result<()> {
+        let certified_key = load(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = certified_key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Reload the certificate whenever the process receives SIGHUP. If it cannot
+/// be loaded, the previous one continues to be used.
+#[cfg(unix)]
+async fn reload_on_sighup(resolver: Arc<CertResolver>) {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Unable to reload the TLS certificate on SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match resolver.reload() {
+            Ok(()) => log::info!("Reloaded TLS certificate from {}", resolver.cert_path),
+            Err(e) => log::error!("Failed to reload TLS certificate: {:#}", e),
+        }
+    }
+}
+
+/// Middleware which redirects requests received over plain HTTP to the same
+/// path over HTTPS
+pub struct RedirectToHttps {
+    /// Scheme, host and (if not 443) port to redirect to
+    origin: String,
+}
+
+impl RedirectToHttps {
+    /// Redirect to the given host, on the port HTTPS is served on
+    pub fn new(host: &str, port: u16) -> RedirectToHttps {
+        let origin = match port {
+            443 => format!("https://{}", host),
+            port => format!("https://{}:{}", host, port),
+        };
+        RedirectToHttps { origin }
+    }
+}
+
+impl<S, B> Transform<S> for RedirectToHttps
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RedirectToHttpsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RedirectToHttpsMiddleware {
+            service,
+            origin: self.origin.clone(),
+        })
+    }
+}
+
+pub struct RedirectToHttpsMiddleware<S> {
+    service: S,
+    origin: String,
+}
+
+impl<S, B> Service for RedirectToHttpsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // Only connections to the TLS listener are marked secure
+        if req.app_config().secure() {
+            return Either::Left(self.service.call(req));
+        }
+
+        let location = match req.uri().path_and_query() {
+            Some(path) => format!("{}{}", self.origin, path),
+            None => format!("{}/", self.origin),
+        };
+        let res = HttpResponse::PermanentRedirect()
+            .header(header::LOCATION, location)
+            .finish()
+            .into_body();
+        Either::Right(ok(req.into_response(res)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_redirect_to_https() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RedirectToHttps::new("example.com", 8443))
+                .route("/graphql", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/graphql?query=%7B__typename%7D")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 308);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            "https://example.com:8443/graphql?query=%7B__typename%7D"
+        );
+    }
+}